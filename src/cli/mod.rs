@@ -0,0 +1,177 @@
+use std::path::PathBuf;
+
+use crate::event_log::Level;
+use crate::focus::WindowFilter;
+use crate::hotkeys::Binding;
+use crate::keyboard_provider::BackendKind;
+use crate::keycodes::Layout;
+use crate::midi::{ControlBinding, Timing};
+use crate::sheet::Mapping;
+
+pub const USAGE: &str = "\
+Usage: miditoqwerty-rs [options]
+
+Options:
+    --log-level <level>     Record and print log entries up to this level: error, warn, info (default), debug, trace
+    --log-file <path>       Append every recorded log entry to a file
+    --log-capacity <n>      Number of entries kept for the event log panel
+    --latency               Print latency statistics to the log every few seconds
+    --latency-csv <path>    Write every latency sample to a CSV file
+    --backend <backend>     Where key events go: native, wayland, xtest (Linux), dry-run or network
+    --dry-run               Same as --backend dry-run, records key events instead of sending them
+    --dry-run-file <path>   Like --dry-run, also writing the recorded events to a file
+    --forward-to <address>  Same as --backend network, forwarding key events to host:port
+    --layout <layout>       Keyboard layout of the OS the keys are typed into: us, uk, de or fr
+    --type-characters       Type the characters of a mapping instead of pressing their keys
+    --target-window <match> Only send keys while a matching window is focused: part of its title,
+                            or class:<name> / process:<name> (X11 and Windows)
+    --hotkey <key>=<command>
+                            Bind a global hotkey, e.g. ctrl+alt+o=toggle-output. Replaces the
+                            default hotkeys, can be repeated. Commands: toggle-output, next-method,
+                            panic, transpose-up, transpose-down, octave-up, octave-down, transpose-reset,
+                            method-<n>, toggle-recording, stop
+    --no-hotkeys            Don't listen for global hotkeys
+    --virtual-port          Start on the virtual MIDI input port other software can send to (Linux, macOS)
+    --udp-midi <address>    Also play MIDI received as UDP datagrams on host:port, e.g. 0.0.0.0:5008
+    --rtp-midi <address>    Also accept RTP-MIDI (AppleMIDI) sessions on host:port and the port after it,
+                            e.g. 0.0.0.0:5004
+    --thru <output>         Forward incoming MIDI to the output whose name contains this, \"virtual\"
+                            for a virtual output port other software can read (Linux, macOS)
+    --control <control>=<command>
+                            Run a command from the instrument instead of playing, where the control is
+                            note:<n>, cc:<n> or pc:<n>, e.g. cc:20=toggle-output. Can be repeated
+    --no-program-change     Don't switch output methods on Program Change
+    --profile <path>        Apply the settings in a profile file at startup
+    --export-sheet <file.mid>
+                            Write the notes of a MIDI file as a virtual piano sheet to <file>.txt and exit
+    --sheet-method <method> Output method whose keys the sheet uses: generic (default) or pv
+    --bpm <n>               Tempo sheets are written in and of the quantize grid, 120 by default
+    --quantize <n>          Move notes of played files to a grid of 1/n notes, e.g. 16 for sixteenths
+    --quantize-strength <percent>
+                            How far notes move towards the grid, 100 by default
+    --humanize <ms>         Shift notes of played files by up to this many milliseconds at random
+    --humanize-velocity <n> Change the velocity of played notes by up to this much at random
+    --remote <address>      Serve the HTTP/WebSocket remote control on host:port, e.g. 127.0.0.1:7879.
+                            There's no authentication, keep it on localhost or a trusted network
+    --device-name <name>    Name of the virtual uinput keyboard (Linux), \"miditoqwerty\" by default
+    --key-server <address>  Only receive forwarded key events on host:port and send them to the OS
+    --print-udev-rule       Print the udev rule and setup for using /dev/uinput without sudo (Linux)
+    -h, --help              Show this message";
+
+#[derive(Debug, Default)]
+pub struct CliOptions {
+    pub log_level: Option<Level>,
+    pub log_file: Option<PathBuf>,
+    pub log_capacity: Option<usize>,
+    pub latency_report: bool,
+    pub latency_csv: Option<PathBuf>,
+    pub backend: Option<BackendKind>,
+    pub dry_run_file: Option<PathBuf>,
+    pub forward_to: Option<String>,
+    pub key_server: Option<String>,
+    pub device_name: Option<String>,
+    pub layout: Option<Layout>,
+    pub type_characters: bool,
+    pub target_window: Option<WindowFilter>,
+    pub hotkeys: Option<Vec<Binding>>,
+    pub no_hotkeys: bool,
+    pub virtual_port: bool,
+    pub thru: Option<String>,
+    pub udp_midi: Option<String>,
+    pub rtp_midi: Option<String>,
+    pub controls: Vec<ControlBinding>,
+    pub no_program_change: bool,
+    pub profile: Option<PathBuf>,
+    pub remote: Option<String>,
+    pub export_sheet: Option<PathBuf>,
+    pub sheet_mapping: Option<Mapping>,
+    pub timing: Timing,
+    pub print_udev_rule: bool,
+    pub help: bool,
+}
+
+impl CliOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = CliOptions::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| format!("{name} requires a value"));
+            match arg.as_str() {
+                "--log-level" => {
+                    let level = value("--log-level")?;
+                    options.log_level = Some(Level::parse(&level).ok_or_else(|| format!("Unknown log level: {level}"))?);
+                }
+                "--log-file" => options.log_file = Some(PathBuf::from(value("--log-file")?)),
+                "--log-capacity" => {
+                    let capacity = value("--log-capacity")?;
+                    options.log_capacity = Some(capacity.parse().map_err(|_| format!("Invalid log capacity: {capacity}"))?);
+                }
+                "--latency" => options.latency_report = true,
+                "--latency-csv" => options.latency_csv = Some(PathBuf::from(value("--latency-csv")?)),
+                "--backend" => {
+                    let backend = value("--backend")?;
+                    options.backend = Some(BackendKind::parse(&backend).ok_or_else(|| format!("Unknown backend: {backend}"))?);
+                }
+                "--dry-run" => options.backend = Some(BackendKind::DryRun),
+                "--dry-run-file" => {
+                    options.backend = Some(BackendKind::DryRun);
+                    options.dry_run_file = Some(PathBuf::from(value("--dry-run-file")?));
+                }
+                "--forward-to" => {
+                    options.backend = Some(BackendKind::Network);
+                    options.forward_to = Some(value("--forward-to")?);
+                }
+                "--key-server" => options.key_server = Some(value("--key-server")?),
+                "--layout" => {
+                    let layout = value("--layout")?;
+                    options.layout = Some(Layout::parse(&layout).ok_or_else(|| format!("Unknown layout: {layout}"))?);
+                }
+                "--type-characters" => options.type_characters = true,
+                "--target-window" => options.target_window = Some(WindowFilter::parse(&value("--target-window")?)?),
+                "--hotkey" => options.hotkeys.get_or_insert_with(Vec::new).push(Binding::parse(&value("--hotkey")?)?),
+                "--no-hotkeys" => options.no_hotkeys = true,
+                "--virtual-port" => options.virtual_port = true,
+                "--thru" => options.thru = Some(value("--thru")?),
+                "--udp-midi" => options.udp_midi = Some(value("--udp-midi")?),
+                "--rtp-midi" => options.rtp_midi = Some(value("--rtp-midi")?),
+                "--control" => options.controls.push(ControlBinding::parse(&value("--control")?)?),
+                "--no-program-change" => options.no_program_change = true,
+                "--profile" => options.profile = Some(PathBuf::from(value("--profile")?)),
+                "--remote" => options.remote = Some(value("--remote")?),
+                "--export-sheet" => options.export_sheet = Some(PathBuf::from(value("--export-sheet")?)),
+                "--sheet-method" => {
+                    let method = value("--sheet-method")?;
+                    options.sheet_mapping = Some(Mapping::parse(&method).ok_or_else(|| format!("Sheets can only be written for generic or pv, not {method}"))?);
+                }
+                "--bpm" => {
+                    let bpm = value("--bpm")?;
+                    options.timing.bpm = bpm.parse().ok().filter(|bpm: &f64| *bpm > 0.0).ok_or_else(|| format!("Invalid tempo: {bpm}"))?;
+                }
+                "--quantize" => {
+                    let grid = value("--quantize")?;
+                    options.timing.grid = grid.parse().ok().filter(|grid: &u32| *grid > 0).ok_or_else(|| format!("Invalid grid: {grid}"))?;
+                }
+                "--quantize-strength" => {
+                    let strength = value("--quantize-strength")?;
+                    let percent = strength.parse::<f64>().ok().filter(|percent| (0.0..=100.0).contains(percent)).ok_or_else(|| format!("Invalid quantize strength: {strength}"))?;
+                    options.timing.strength = percent / 100.0;
+                }
+                "--humanize" => {
+                    let jitter = value("--humanize")?;
+                    options.timing.jitter = jitter.parse().map_err(|_| format!("Invalid humanize amount: {jitter}"))?;
+                }
+                "--humanize-velocity" => {
+                    let jitter = value("--humanize-velocity")?;
+                    options.timing.velocity_jitter = jitter.parse().ok().filter(|jitter| *jitter <= 127).ok_or_else(|| format!("Invalid velocity amount: {jitter}"))?;
+                }
+                "--device-name" => options.device_name = Some(value("--device-name")?),
+                "--print-udev-rule" => options.print_udev_rule = true,
+                "-h" | "--help" => options.help = true,
+                other => return Err(format!("Unknown argument: {other}")),
+            }
+        }
+
+        Ok(options)
+    }
+}
@@ -0,0 +1,92 @@
+// Things the app can be told to do without its window having focus, from hotkeys, MIDI controls and the like
+
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppCommand {
+    ToggleOutput,
+    NextMethod,
+    Panic, // releases every key and forgets held notes and pedals
+    Transpose(i8), // relative, in semitones
+    ResetTranspose,
+    SelectMethod(u8), // index into the output methods, as sent by Program Change
+    ToggleRecording,
+    StopPlayback,
+}
+
+impl AppCommand {
+    pub const NAMES: &'static str = "toggle-output, next-method, panic, transpose-up, transpose-down, octave-up, octave-down, transpose-reset, method-<n>, toggle-recording, stop";
+
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.to_ascii_lowercase();
+        // Methods are numbered from 1 for people, from 0 like programs internally
+        if let Some(number) = s.strip_prefix("method-") {
+            return number.parse::<u8>().ok().filter(|number| *number > 0).map(|number| AppCommand::SelectMethod(number - 1));
+        }
+        let command = match s.as_str() {
+            "toggle-output" => AppCommand::ToggleOutput,
+            "next-method" => AppCommand::NextMethod,
+            "panic" => AppCommand::Panic,
+            "transpose-up" => AppCommand::Transpose(1),
+            "transpose-down" => AppCommand::Transpose(-1),
+            "octave-up" => AppCommand::Transpose(12),
+            "octave-down" => AppCommand::Transpose(-12),
+            "transpose-reset" => AppCommand::ResetTranspose,
+            "toggle-recording" => AppCommand::ToggleRecording,
+            "stop" => AppCommand::StopPlayback,
+            _ => return None,
+        };
+        Some(command)
+    }
+}
+impl std::fmt::Display for AppCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppCommand::ToggleOutput => write!(f, "toggle-output"),
+            AppCommand::NextMethod => write!(f, "next-method"),
+            AppCommand::Panic => write!(f, "panic"),
+            AppCommand::Transpose(semitones) => write!(f, "transpose {semitones:+}"),
+            AppCommand::ResetTranspose => write!(f, "transpose-reset"),
+            AppCommand::SelectMethod(index) => write!(f, "method-{}", *index as u16 + 1),
+            AppCommand::ToggleRecording => write!(f, "toggle-recording"),
+            AppCommand::StopPlayback => write!(f, "stop"),
+        }
+    }
+}
+
+// Commands from the remote control, which can also name files on the machine running the app
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemoteCommand {
+    App(AppCommand),
+    LoadProfile(PathBuf),
+    Play(PathBuf), // a .mid file
+}
+
+impl RemoteCommand {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        let (name, argument) = s.split_once(char::is_whitespace).map_or((s, ""), |(name, argument)| (name, argument.trim()));
+        match name.to_ascii_lowercase().as_str() {
+            "load-profile" | "play" if argument.is_empty() => Err(format!("{name} needs a file")),
+            "load-profile" => Ok(RemoteCommand::LoadProfile(argument.into())),
+            "play" => Ok(RemoteCommand::Play(argument.into())),
+            _ => AppCommand::parse(s).map(RemoteCommand::App)
+                .ok_or_else(|| format!("Unknown command {s:?}, expected one of: {}, load-profile <file>, play <file>", AppCommand::NAMES)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_remote_commands() {
+        assert_eq!(RemoteCommand::parse("panic"), Ok(RemoteCommand::App(AppCommand::Panic)));
+        assert_eq!(RemoteCommand::parse(" Method-2\n"), Ok(RemoteCommand::App(AppCommand::SelectMethod(1))));
+        assert_eq!(RemoteCommand::parse("play songs/Für Elise.mid"), Ok(RemoteCommand::Play("songs/Für Elise.mid".into())));
+        assert_eq!(RemoteCommand::parse("load-profile  roblox.txt "), Ok(RemoteCommand::LoadProfile("roblox.txt".into())));
+        assert!(RemoteCommand::parse("play").is_err());
+        assert!(RemoteCommand::parse("dance").unwrap_err().contains("toggle-output"));
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+const DEFAULT_CAPACITY: usize = 1000;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+    Trace = 4
+}
+impl Level {
+    pub const ALL: [Level; 5] = [Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Level> {
+        Level::ALL.into_iter().find(|level| level.name().eq_ignore_ascii_case(s))
+    }
+
+    fn from_u8(value: u8) -> Level {
+        *Level::ALL.get(value as usize).unwrap_or(&Level::Trace)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    MidiIn, // messages coming from the MIDI port
+    KeyOut, // key events sent to the virtual keyboard
+    App     // connections, settings, everything else
+}
+impl Category {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::MidiIn => "MIDI in",
+            Category::KeyOut => "Keys out",
+            Category::App => "App",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub time: Duration, // since the log was created
+    pub level: Level,
+    pub category: Category,
+    pub message: String
+}
+impl std::fmt::Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{:>9.3}] {:<5} {}: {}", self.time.as_secs_f64(), self.level.name(), self.category.name(), self.message)
+    }
+}
+
+pub struct EventLog {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+    stdout_level: Level,
+    file: Option<BufWriter<File>>,
+    flushed: Instant, // when the file was last flushed
+    started: Instant,
+}
+impl EventLog {
+    fn new() -> Self {
+        EventLog {
+            entries: VecDeque::with_capacity(DEFAULT_CAPACITY),
+            capacity: DEFAULT_CAPACITY,
+            stdout_level: Level::Info,
+            file: None,
+            flushed: Instant::now(),
+            started: Instant::now(),
+        }
+    }
+
+    fn push(&mut self, level: Level, category: Category, message: String) {
+        let entry = Entry { time: self.started.elapsed(), level, category, message };
+
+        if level <= self.stdout_level {
+            println!("{}", entry);
+        }
+        if let Some(file) = self.file.as_mut() {
+            // A broken log file shouldn't take the MIDI thread down with it
+            if writeln!(file, "{}", entry).is_err() {
+                self.file = None;
+            }
+        }
+        // Errors are often the last thing logged before exiting
+        if level == Level::Error || self.flushed.elapsed() >= FLUSH_INTERVAL {
+            self.flush();
+        }
+
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    fn flush(&mut self) {
+        self.flushed = Instant::now();
+        if self.file.as_mut().is_some_and(|file| file.flush().is_err()) {
+            self.file = None;
+        }
+    }
+}
+
+// Everything at or below this level is recorded, checked before formatting so disabled levels cost nothing.
+// Debug logs every MIDI message, so it's only recorded once asked for
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static LOG: OnceLock<Mutex<EventLog>> = OnceLock::new();
+
+fn log() -> &'static Mutex<EventLog> {
+    LOG.get_or_init(|| Mutex::new(EventLog::new()))
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+pub fn push(level: Level, category: Category, message: String) {
+    if let Ok(mut log) = log().lock() {
+        log.push(level, category, message);
+    }
+}
+
+pub fn max_level() -> Level {
+    Level::from_u8(MAX_LEVEL.load(Ordering::Relaxed))
+}
+
+/// Sets the most verbose level that gets recorded at all.
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Sets the most verbose level that also gets printed to stdout.
+pub fn set_stdout_level(level: Level) {
+    log().lock().unwrap().stdout_level = level;
+    if level > max_level() {
+        set_max_level(level);
+    }
+}
+
+pub fn set_capacity(capacity: usize) {
+    let mut log = log().lock().unwrap();
+    log.capacity = capacity.max(1);
+    while log.entries.len() > log.capacity {
+        log.entries.pop_front();
+    }
+}
+
+pub fn set_file(path: &Path) -> Result<(), io::Error> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    log().lock().unwrap().file = Some(BufWriter::new(file));
+    Ok(())
+}
+
+/// Writes out whatever the log file is still buffering.
+pub fn flush() {
+    log().lock().unwrap().flush();
+}
+
+pub fn clear() {
+    log().lock().unwrap().entries.clear();
+}
+
+#[derive(Debug, Clone)]
+pub struct Filter {
+    pub level: Level,
+    pub midi_in: bool,
+    pub key_out: bool,
+    pub app: bool,
+}
+impl Default for Filter {
+    fn default() -> Self {
+        Filter { level: Level::Info, midi_in: true, key_out: true, app: true }
+    }
+}
+impl Filter {
+    pub fn accepts(&self, entry: &Entry) -> bool {
+        entry.level <= self.level && match entry.category {
+            Category::MidiIn => self.midi_in,
+            Category::KeyOut => self.key_out,
+            Category::App => self.app,
+        }
+    }
+}
+
+pub fn snapshot(filter: &Filter) -> Vec<Entry> {
+    let mut log = log().lock().unwrap();
+    // Whoever looks at the log expects the file to show the same
+    log.flush();
+    log.entries.iter().filter(|entry| filter.accepts(entry)).cloned().collect()
+}
+
+macro_rules! log_at {
+    ($level:expr, $category:expr, $($arg:tt)+) => {
+        if $crate::event_log::enabled($level) {
+            $crate::event_log::push($level, $category, format!($($arg)+));
+        }
+    };
+}
+
+macro_rules! log_error {
+    ($category:ident, $($arg:tt)+) => { log_at!($crate::event_log::Level::Error, $crate::event_log::Category::$category, $($arg)+) };
+}
+macro_rules! log_warn {
+    ($category:ident, $($arg:tt)+) => { log_at!($crate::event_log::Level::Warn, $crate::event_log::Category::$category, $($arg)+) };
+}
+macro_rules! log_info {
+    ($category:ident, $($arg:tt)+) => { log_at!($crate::event_log::Level::Info, $crate::event_log::Category::$category, $($arg)+) };
+}
+macro_rules! log_debug {
+    ($category:ident, $($arg:tt)+) => { log_at!($crate::event_log::Level::Debug, $crate::event_log::Category::$category, $($arg)+) };
+}
+macro_rules! log_trace {
+    ($category:ident, $($arg:tt)+) => { log_at!($crate::event_log::Level::Trace, $crate::event_log::Category::$category, $($arg)+) };
+}
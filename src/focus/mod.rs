@@ -0,0 +1,226 @@
+// Finds out which window has focus, so keys only go to the game they're meant for and
+// alt-tabbing away mid-song doesn't type into chat or an editor
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+mod x11;
+#[cfg(target_os = "windows")]
+mod windows;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WindowInfo {
+    pub title: String,
+    pub class: String, // WM_CLASS on X11, the window class name on Windows
+    pub process: String, // executable name
+}
+impl std::fmt::Display for WindowInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} (class {:?}, process {:?})", self.title, self.class, self.process)
+    }
+}
+
+pub trait FocusQuery: Send {
+    fn focused_window(&mut self) -> Result<Option<WindowInfo>, io::Error>;
+}
+
+#[cfg(target_os = "linux")]
+pub fn create_focus_query() -> Result<Box<dyn FocusQuery>, io::Error> {
+    Ok(Box::new(x11::X11Focus::new()?))
+}
+#[cfg(target_os = "windows")]
+pub fn create_focus_query() -> Result<Box<dyn FocusQuery>, io::Error> {
+    Ok(Box::new(windows::WindowsFocus))
+}
+#[cfg(target_os = "macos")]
+pub fn create_focus_query() -> Result<Box<dyn FocusQuery>, io::Error> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Targeting a window isn't supported on macOS yet"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchField {
+    Title,
+    Class,
+    Process,
+}
+
+impl MatchField {
+    pub const ALL: [MatchField; 3] = [MatchField::Title, MatchField::Class, MatchField::Process];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MatchField::Title => "title",
+            MatchField::Class => "class",
+            MatchField::Process => "process",
+        }
+    }
+}
+
+// Case-insensitive substring match against one property of the focused window
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowFilter {
+    pub field: MatchField,
+    pub pattern: String,
+}
+
+impl WindowFilter {
+    // "title:Roblox", "class:steam_app_123" or "process:RobloxPlayerBeta.exe", a plain pattern matches the title
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (field, pattern) = match s.split_once(':') {
+            Some((field, pattern)) => match MatchField::ALL.into_iter().find(|f| f.name() == field.to_ascii_lowercase()) {
+                Some(field) => (field, pattern),
+                None => (MatchField::Title, s),
+            },
+            None => (MatchField::Title, s),
+        };
+
+        if pattern.trim().is_empty() {
+            return Err(format!("Empty window {} to match", field.name()));
+        }
+        Ok(WindowFilter { field, pattern: pattern.to_lowercase() })
+    }
+
+    pub fn matches(&self, window: &WindowInfo) -> bool {
+        let value = match self.field {
+            MatchField::Title => &window.title,
+            MatchField::Class => &window.class,
+            MatchField::Process => &window.process,
+        };
+        value.to_lowercase().contains(&self.pattern)
+    }
+}
+impl std::fmt::Display for WindowFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.field.name(), self.pattern)
+    }
+}
+
+// Polls the focused window in the background and pauses output while the target isn't focused
+#[derive(Clone)]
+pub struct FocusGuard {
+    filter: Arc<Mutex<Option<WindowFilter>>>,
+    allowed: Arc<AtomicBool>,
+    focused: Arc<Mutex<Option<WindowInfo>>>,
+}
+
+impl FocusGuard {
+    // `on_leave` runs on the polling thread whenever focus moves away from the target
+    pub fn start(mut query: Box<dyn FocusQuery>, filter: Option<WindowFilter>, on_leave: impl Fn() + Send + 'static) -> Self {
+        let guard = FocusGuard {
+            filter: Arc::new(Mutex::new(filter)),
+            allowed: Arc::new(AtomicBool::new(true)),
+            focused: Arc::new(Mutex::new(None)),
+        };
+
+        let polled = guard.clone();
+        thread::spawn(move || {
+            let mut last_error = None;
+            loop {
+                let window = match query.focused_window() {
+                    Ok(window) => {
+                        last_error = None;
+                        window
+                    }
+                    Err(err) => {
+                        let message = err.to_string();
+                        if last_error.as_ref() != Some(&message) {
+                            log_warn!(App, "Unable to find the focused window: {}", message);
+                            last_error = Some(message);
+                        }
+                        None
+                    }
+                };
+                polled.update(window, &on_leave);
+                thread::sleep(POLL_INTERVAL);
+            }
+        });
+
+        guard
+    }
+
+    fn update(&self, window: Option<WindowInfo>, on_leave: &impl Fn()) {
+        let allowed = match &*self.filter.lock().unwrap() {
+            None => true,
+            Some(filter) => window.as_ref().is_some_and(|window| filter.matches(window)),
+        };
+
+        let was_allowed = self.allowed.swap(allowed, Ordering::SeqCst);
+        if was_allowed && !allowed {
+            log_info!(App, "Target window lost focus to {}, pausing output", window.as_ref().map(|w| w.to_string()).unwrap_or("nothing".to_owned()));
+            on_leave();
+        } else if !was_allowed && allowed {
+            log_info!(App, "Target window focused, resuming output");
+        }
+
+        *self.focused.lock().unwrap() = window;
+    }
+
+    // Takes effect on the next poll
+    pub fn set_filter(&self, filter: Option<WindowFilter>) {
+        match &filter {
+            Some(filter) => log_info!(App, "Only sending keys while a window with {} is focused", filter),
+            None => log_info!(App, "Sending keys to any focused window"),
+        }
+        *self.filter.lock().unwrap() = filter;
+    }
+
+    pub fn filter(&self) -> Option<WindowFilter> {
+        self.filter.lock().unwrap().clone()
+    }
+
+    // Whether keys may be sent right now, always true without a filter
+    pub fn allowed(&self) -> bool {
+        self.allowed.load(Ordering::SeqCst)
+    }
+
+    pub fn focused_window(&self) -> Option<WindowInfo> {
+        self.focused.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(title: &str, class: &str, process: &str) -> WindowInfo {
+        WindowInfo { title: title.to_owned(), class: class.to_owned(), process: process.to_owned() }
+    }
+
+    #[test]
+    fn filters() {
+        let roblox = window("Roblox", "RobloxApp", "RobloxPlayerBeta.exe");
+        assert!(WindowFilter::parse("roblox").unwrap().matches(&roblox));
+        assert!(WindowFilter::parse("process:robloxplayer").unwrap().matches(&roblox));
+        assert!(!WindowFilter::parse("class:firefox").unwrap().matches(&roblox));
+        // Unknown prefixes are part of the title
+        assert!(WindowFilter::parse("Re: Roblox").unwrap().matches(&window("Re: Roblox - Mail", "", "")));
+        assert!(WindowFilter::parse("title: ").is_err());
+    }
+
+    #[test]
+    fn releases_when_focus_leaves() {
+        let guard = FocusGuard {
+            filter: Arc::new(Mutex::new(Some(WindowFilter::parse("game").unwrap()))),
+            allowed: Arc::new(AtomicBool::new(true)),
+            focused: Arc::new(Mutex::new(None)),
+        };
+        let left = AtomicBool::new(false);
+        let on_leave = || left.store(true, Ordering::SeqCst);
+
+        guard.update(Some(window("Game", "", "")), &on_leave);
+        assert!(guard.allowed() && !left.load(Ordering::SeqCst));
+
+        guard.update(Some(window("Chat", "", "")), &on_leave);
+        assert!(!guard.allowed() && left.load(Ordering::SeqCst));
+
+        guard.set_filter(None);
+        guard.update(None, &on_leave);
+        assert!(guard.allowed());
+    }
+}
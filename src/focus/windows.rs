@@ -0,0 +1,60 @@
+use std::io;
+use std::path::Path;
+
+use winapi::shared::minwindef::DWORD;
+use winapi::um::handleapi::CloseHandle;
+use winapi::um::processthreadsapi::OpenProcess;
+use winapi::um::winbase::QueryFullProcessImageNameW;
+use winapi::um::winnt::PROCESS_QUERY_LIMITED_INFORMATION;
+use winapi::um::winuser::{GetClassNameW, GetForegroundWindow, GetWindowTextW, GetWindowThreadProcessId};
+
+use super::{FocusQuery, WindowInfo};
+
+pub struct WindowsFocus;
+
+fn process_name(pid: DWORD) -> String {
+    let mut buf = [0u16; 1024];
+    let mut len = buf.len() as DWORD;
+
+    let queried = unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if process.is_null() {
+            return String::new();
+        }
+        let queried = QueryFullProcessImageNameW(process, 0, buf.as_mut_ptr(), &mut len);
+        CloseHandle(process);
+        queried
+    };
+    if queried == 0 {
+        return String::new();
+    }
+
+    let path = String::from_utf16_lossy(&buf[..len as usize]);
+    Path::new(&path).file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or(path)
+}
+
+impl FocusQuery for WindowsFocus {
+    fn focused_window(&mut self) -> Result<Option<WindowInfo>, io::Error> {
+        let window = unsafe { GetForegroundWindow() };
+        if window.is_null() {
+            return Ok(None);
+        }
+
+        let mut title = [0u16; 512];
+        let mut class = [0u16; 256];
+        let mut pid: DWORD = 0;
+        let (title_len, class_len) = unsafe {
+            GetWindowThreadProcessId(window, &mut pid);
+            (
+                GetWindowTextW(window, title.as_mut_ptr(), title.len() as i32).max(0) as usize,
+                GetClassNameW(window, class.as_mut_ptr(), class.len() as i32).max(0) as usize,
+            )
+        };
+
+        Ok(Some(WindowInfo {
+            title: String::from_utf16_lossy(&title[..title_len]),
+            class: String::from_utf16_lossy(&class[..class_len]),
+            process: process_name(pid),
+        }))
+    }
+}
@@ -0,0 +1,138 @@
+// Asks the X server for the active window, through EWMH when a window manager provides it and
+// the input focus otherwise (bare Xvfb), which keeps it testable without a desktop.
+
+use std::fs;
+use std::io;
+
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{Atom, AtomEnum, ConnectionExt as _, GetPropertyReply, Window};
+use x11rb::rust_connection::RustConnection;
+
+use super::{FocusQuery, WindowInfo};
+
+// Input focus values that aren't windows
+const NO_FOCUS: Window = 0;
+const POINTER_ROOT: Window = 1;
+// Focus usually lands on a child of the toplevel window, which is the one with the properties
+const MAX_PARENTS: usize = 8;
+
+fn x11_error(err: impl std::fmt::Display) -> io::Error {
+    io::Error::other(format!("X11: {err}"))
+}
+
+pub struct X11Focus {
+    connection: RustConnection,
+    root: Window,
+    net_active_window: Atom,
+    net_wm_name: Atom,
+    net_wm_pid: Atom,
+    utf8_string: Atom,
+}
+
+impl X11Focus {
+    pub fn new() -> Result<Self, io::Error> {
+        let (connection, screen) = x11rb::connect(None).map_err(x11_error)?;
+        let root = connection.setup().roots[screen].root;
+
+        let atom = |name: &[u8]| -> Result<Atom, io::Error> {
+            Ok(connection.intern_atom(false, name).map_err(x11_error)?.reply().map_err(x11_error)?.atom)
+        };
+        let net_active_window = atom(b"_NET_ACTIVE_WINDOW")?;
+        let net_wm_name = atom(b"_NET_WM_NAME")?;
+        let net_wm_pid = atom(b"_NET_WM_PID")?;
+        let utf8_string = atom(b"UTF8_STRING")?;
+
+        Ok(X11Focus { connection, root, net_active_window, net_wm_name, net_wm_pid, utf8_string })
+    }
+
+    fn property(&self, window: Window, property: impl Into<Atom>, kind: impl Into<Atom>) -> Result<GetPropertyReply, io::Error> {
+        self.connection.get_property(false, window, property, kind, 0, 1024)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)
+    }
+
+    fn active_window(&self) -> Result<Option<Window>, io::Error> {
+        let active = self.property(self.root, self.net_active_window, AtomEnum::WINDOW)?;
+        if let Some(window) = active.value32().and_then(|mut values| values.next()) {
+            return Ok((window != NO_FOCUS).then_some(window));
+        }
+
+        let focus = self.connection.get_input_focus().map_err(x11_error)?.reply().map_err(x11_error)?.focus;
+        Ok((focus != NO_FOCUS && focus != POINTER_ROOT && focus != self.root).then_some(focus))
+    }
+
+    fn describe(&self, window: Window) -> Result<WindowInfo, io::Error> {
+        let mut title = String::from_utf8_lossy(&self.property(window, self.net_wm_name, self.utf8_string)?.value).into_owned();
+        if title.is_empty() {
+            title = String::from_utf8_lossy(&self.property(window, AtomEnum::WM_NAME, AtomEnum::ANY)?.value).into_owned();
+        }
+
+        // Instance and class name, both null-terminated
+        let class = self.property(window, AtomEnum::WM_CLASS, AtomEnum::STRING)?.value;
+        let mut names = class.split(|byte| *byte == 0).filter(|name| !name.is_empty());
+        let instance = names.next();
+        let class = names.next().or(instance).map(|name| String::from_utf8_lossy(name).into_owned()).unwrap_or_default();
+
+        let process = self.property(window, self.net_wm_pid, AtomEnum::CARDINAL)?
+            .value32()
+            .and_then(|mut values| values.next())
+            .and_then(|pid| fs::read_to_string(format!("/proc/{pid}/comm")).ok())
+            .map(|comm| comm.trim().to_owned())
+            .unwrap_or_default();
+
+        Ok(WindowInfo { title, class, process })
+    }
+}
+
+impl FocusQuery for X11Focus {
+    fn focused_window(&mut self) -> Result<Option<WindowInfo>, io::Error> {
+        let Some(mut window) = self.active_window()? else {
+            return Ok(None);
+        };
+
+        for _ in 0..MAX_PARENTS {
+            let info = self.describe(window)?;
+            if !info.title.is_empty() || !info.class.is_empty() {
+                return Ok(Some(info));
+            }
+
+            let parent = self.connection.query_tree(window).map_err(x11_error)?.reply().map_err(x11_error)?.parent;
+            if parent == self.root || parent == NO_FOCUS {
+                return Ok(Some(info));
+            }
+            window = parent;
+        }
+        self.describe(window).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use x11rb::protocol::xproto::{CreateWindowAux, InputFocus, PropMode, WindowClass};
+    use x11rb::wrapper::ConnectionExt as _;
+
+    use super::*;
+
+    // Needs an X server, e.g. `xvfb-run cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn finds_focused_window() {
+        let (connection, screen) = x11rb::connect(None).expect("No X server available");
+        let screen = &connection.setup().roots[screen];
+
+        let window = connection.generate_id().unwrap();
+        connection.create_window(
+            x11rb::COPY_DEPTH_FROM_PARENT, window, screen.root, 0, 0, 100, 100, 0,
+            WindowClass::INPUT_OUTPUT, x11rb::COPY_FROM_PARENT, &CreateWindowAux::new(),
+        ).unwrap();
+        connection.change_property8(PropMode::REPLACE, window, AtomEnum::WM_NAME, AtomEnum::STRING, b"Focus test").unwrap();
+        connection.change_property8(PropMode::REPLACE, window, AtomEnum::WM_CLASS, AtomEnum::STRING, b"focus-test\0FocusTest\0").unwrap();
+        connection.map_window(window).unwrap();
+        connection.set_input_focus(InputFocus::PARENT, window, x11rb::CURRENT_TIME).unwrap().check().unwrap();
+
+        let focused = X11Focus::new().unwrap().focused_window().unwrap().expect("No focused window");
+        assert_eq!(focused.title, "Focus test");
+        assert_eq!(focused.class, "FocusTest");
+    }
+}
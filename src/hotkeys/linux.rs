@@ -0,0 +1,104 @@
+// Reads the keyboards in /dev/input without grabbing them, which needs the same "input" group
+// membership as the uinput output. Keyboards plugged in later aren't picked up.
+
+use std::io;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use evdev::{Device, InputEventKind};
+
+use super::{Binding, HotkeyState};
+use crate::commands::AppCommand;
+use crate::keycodes::KeyName;
+
+const KEY_REPEAT: i32 = 2;
+
+fn key_name(code: u16) -> Option<KeyName> {
+    KeyName::ALL.iter().copied().find(|id| id.native() == code as u32)
+}
+
+pub fn start(bindings: Vec<Binding>, commands: Sender<AppCommand>, own_device: &str) -> Result<(), io::Error> {
+    let keyboards: Vec<(PathBuf, Device)> = evdev::enumerate()
+        .filter(|(_, device)| device.supported_keys().is_some_and(|keys| keys.contains(evdev::Key::KEY_A)))
+        .filter(|(_, device)| device.name() != Some(own_device))
+        .collect();
+
+    if keyboards.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "No readable keyboards in /dev/input, global hotkeys need this user to be in the \"input\" group",
+        ));
+    }
+
+    for (path, device) in keyboards {
+        watch(path, device, bindings.clone(), commands.clone());
+    }
+    Ok(())
+}
+
+pub fn watch(path: PathBuf, mut device: Device, bindings: Vec<Binding>, commands: Sender<AppCommand>) {
+    log_info!(App, "Listening for hotkeys on {} ({})", device.name().unwrap_or("unnamed keyboard"), path.display());
+
+    thread::spawn(move || {
+        let mut state = HotkeyState::new(bindings);
+        loop {
+            let events = match device.fetch_events() {
+                Ok(events) => events,
+                Err(err) => {
+                    log_warn!(App, "Stopped listening for hotkeys on {}: {}", path.display(), err);
+                    return;
+                }
+            };
+
+            for event in events {
+                let InputEventKind::Key(key) = event.kind() else { continue };
+                if event.value() == KEY_REPEAT {
+                    continue;
+                }
+                let Some(id) = key_name(key.code()) else { continue };
+
+                if let Some(command) = state.key(id, event.value() == 1) {
+                    log_debug!(App, "Hotkey pressed on {}: {}", path.display(), command);
+                    if commands.send(command).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use evdev::uinput::VirtualDeviceBuilder;
+    use evdev::{AttributeSet, EventType, InputEvent, Key};
+
+    use super::*;
+    use crate::hotkeys::default_bindings;
+
+    // Needs write access to /dev/uinput and read access to the created device
+    #[test]
+    #[ignore]
+    fn hotkey_from_virtual_keyboard() {
+        let keys = AttributeSet::from_iter([Key::KEY_LEFTCTRL, Key::KEY_LEFTALT, Key::KEY_O, Key::KEY_A]);
+        let mut keyboard = VirtualDeviceBuilder::new().unwrap()
+            .name("miditoqwerty hotkey test")
+            .with_keys(&keys).unwrap()
+            .build().unwrap();
+
+        let path = keyboard.enumerate_dev_nodes_blocking().unwrap().next().unwrap().unwrap();
+        let (tx, rx) = mpsc::channel();
+        watch(path.clone(), Device::open(&path).unwrap(), default_bindings(), tx);
+        std::thread::sleep(Duration::from_millis(200)); // let the watcher start reading
+
+        for (key, value) in [(Key::KEY_LEFTCTRL, 1), (Key::KEY_LEFTALT, 1), (Key::KEY_O, 1), (Key::KEY_O, 0), (Key::KEY_LEFTALT, 0), (Key::KEY_LEFTCTRL, 0)] {
+            keyboard.emit(&[InputEvent::new(EventType::KEY, key.code(), value)]).unwrap();
+        }
+
+        assert_eq!(rx.recv_timeout(Duration::from_secs(2)), Ok(AppCommand::ToggleOutput));
+    }
+}
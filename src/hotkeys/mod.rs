@@ -0,0 +1,181 @@
+// Global hotkeys, so output can be controlled without focusing our window and taking focus from the game
+
+use std::io;
+use std::sync::mpsc::Sender;
+
+use crate::commands::AppCommand;
+use crate::keycodes::KeyName;
+
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "windows")]
+mod windows;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    pub meta: bool,
+}
+
+impl Modifiers {
+    // Left and right count the same, returns false for keys that aren't modifiers
+    fn set(&mut self, key: KeyName, down: bool) -> bool {
+        let modifier = match key {
+            KeyName::LeftCtrl | KeyName::RightCtrl => &mut self.ctrl,
+            KeyName::Shift | KeyName::RightShift => &mut self.shift,
+            KeyName::LeftAlt | KeyName::RightAlt => &mut self.alt,
+            KeyName::LeftMeta | KeyName::RightMeta => &mut self.meta,
+            _ => return false,
+        };
+        *modifier = down;
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hotkey {
+    pub modifiers: Modifiers,
+    pub key: KeyName,
+}
+
+impl Hotkey {
+    // Modifiers and a key name joined by "+", e.g. "ctrl+alt+o" or "ctrl+shift+up"
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut modifiers = Modifiers::default();
+        let mut parts: Vec<&str> = s.split('+').map(str::trim).collect();
+        let key = parts.pop().filter(|key| !key.is_empty()).ok_or_else(|| format!("Hotkey {s:?} has no key"))?;
+
+        for modifier in parts {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => modifiers.ctrl = true,
+                "shift" => modifiers.shift = true,
+                "alt" => modifiers.alt = true,
+                "meta" | "super" | "win" => modifiers.meta = true,
+                other => return Err(format!("Unknown modifier {other:?} in hotkey {s:?}")),
+            }
+        }
+
+        let key = KeyName::from_name(&key.to_ascii_lowercase()).ok_or_else(|| format!("Unknown key {key:?} in hotkey {s:?}"))?;
+        if key.is_modifier() {
+            return Err(format!("Hotkey {s:?} needs a key that isn't a modifier"));
+        }
+        Ok(Hotkey { modifiers, key })
+    }
+}
+impl std::fmt::Display for Hotkey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Modifiers { ctrl, shift, alt, meta } = self.modifiers;
+        for (held, name) in [(ctrl, "ctrl"), (shift, "shift"), (alt, "alt"), (meta, "meta")] {
+            if held {
+                write!(f, "{name}+")?;
+            }
+        }
+        write!(f, "{}", self.key.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Binding {
+    pub hotkey: Hotkey,
+    pub command: AppCommand,
+}
+
+impl Binding {
+    // "ctrl+alt+o=toggle-output"
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (hotkey, command) = s.split_once('=').ok_or_else(|| format!("Expected <hotkey>=<command>, got {s:?}"))?;
+        let command = AppCommand::parse(command.trim())
+            .ok_or_else(|| format!("Unknown command {:?}, expected one of: {}", command.trim(), AppCommand::NAMES))?;
+        Ok(Binding { hotkey: Hotkey::parse(hotkey)?, command })
+    }
+}
+impl std::fmt::Display for Binding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} = {}", self.hotkey, self.command)
+    }
+}
+
+pub fn default_bindings() -> Vec<Binding> {
+    [
+        "ctrl+alt+o=toggle-output",
+        "ctrl+alt+m=next-method",
+        "ctrl+alt+p=panic",
+        "ctrl+alt+up=transpose-up",
+        "ctrl+alt+down=transpose-down",
+        "ctrl+alt+0=transpose-reset",
+    ].iter().map(|binding| Binding::parse(binding).expect("Invalid default hotkey")).collect()
+}
+
+// Follows the modifiers of one keyboard and reports bindings as their key goes down
+pub struct HotkeyState {
+    modifiers: Modifiers,
+    bindings: Vec<Binding>,
+}
+
+impl HotkeyState {
+    pub fn new(bindings: Vec<Binding>) -> Self {
+        HotkeyState { modifiers: Modifiers::default(), bindings }
+    }
+
+    pub fn key(&mut self, key: KeyName, down: bool) -> Option<AppCommand> {
+        if self.modifiers.set(key, down) || !down {
+            return None;
+        }
+        self.bindings.iter()
+            .find(|binding| binding.hotkey.key == key && binding.hotkey.modifiers == self.modifiers)
+            .map(|binding| binding.command)
+    }
+}
+
+// Starts listening in the background, sending the command of every pressed binding to `commands`.
+// `own_device` is the name of our uinput keyboard, whose keys must not count
+#[cfg(target_os = "linux")]
+pub fn start(bindings: Vec<Binding>, commands: Sender<AppCommand>, own_device: &str) -> Result<(), io::Error> {
+    linux::start(bindings, commands, own_device)
+}
+#[cfg(target_os = "windows")]
+pub fn start(bindings: Vec<Binding>, commands: Sender<AppCommand>, _own_device: &str) -> Result<(), io::Error> {
+    windows::start(bindings, commands)
+}
+#[cfg(target_os = "macos")]
+pub fn start(_bindings: Vec<Binding>, _commands: Sender<AppCommand>, _own_device: &str) -> Result<(), io::Error> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Global hotkeys aren't supported on macOS yet"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_bindings() {
+        let binding = Binding::parse("Ctrl+Alt+O = toggle-output").unwrap();
+        assert_eq!(binding.hotkey.key, KeyName::O);
+        assert_eq!(binding.hotkey.modifiers, Modifiers { ctrl: true, alt: true, ..Default::default() });
+        assert_eq!(binding.command, AppCommand::ToggleOutput);
+        assert_eq!(binding.to_string(), "ctrl+alt+o = toggle-output");
+
+        assert!(Binding::parse("ctrl+alt+o").is_err());
+        assert!(Binding::parse("ctrl+hyper+o=panic").is_err());
+        assert!(Binding::parse("ctrl+shift=panic").is_err());
+        assert!(Binding::parse("ctrl+o=explode").is_err());
+    }
+
+    #[test]
+    fn fires_on_exact_modifiers() {
+        let mut state = HotkeyState::new(default_bindings());
+        assert_eq!(state.key(KeyName::O, true), None);
+        assert_eq!(state.key(KeyName::O, false), None);
+
+        state.key(KeyName::LeftCtrl, true);
+        state.key(KeyName::RightAlt, true);
+        assert_eq!(state.key(KeyName::Up, true), Some(AppCommand::Transpose(1)));
+        assert_eq!(state.key(KeyName::Up, false), None);
+
+        state.key(KeyName::Shift, true);
+        assert_eq!(state.key(KeyName::O, true), None);
+        state.key(KeyName::Shift, false);
+        assert_eq!(state.key(KeyName::O, true), Some(AppCommand::ToggleOutput));
+    }
+}
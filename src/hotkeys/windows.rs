@@ -0,0 +1,49 @@
+use std::io;
+use std::ptr::null_mut;
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use winapi::um::winuser::{
+    GetMessageW, MapVirtualKeyW, RegisterHotKey, MAPVK_VSC_TO_VK_EX, MOD_ALT, MOD_CONTROL, MOD_NOREPEAT, MOD_SHIFT, MOD_WIN, MSG, WM_HOTKEY,
+};
+
+use super::{Binding, Modifiers};
+use crate::commands::AppCommand;
+
+fn modifier_flags(modifiers: Modifiers) -> u32 {
+    let mut flags = MOD_NOREPEAT as u32;
+    if modifiers.ctrl { flags |= MOD_CONTROL as u32 }
+    if modifiers.shift { flags |= MOD_SHIFT as u32 }
+    if modifiers.alt { flags |= MOD_ALT as u32 }
+    if modifiers.meta { flags |= MOD_WIN as u32 }
+    flags
+}
+
+// Hotkeys belong to the thread registering them, which also has to receive their messages
+pub fn start(bindings: Vec<Binding>, commands: Sender<AppCommand>) -> Result<(), io::Error> {
+    thread::spawn(move || {
+        for (id, binding) in bindings.iter().enumerate() {
+            // Our scancodes keep the 0xe0 prefix of extended keys, which MAPVK_VSC_TO_VK_EX understands
+            let vk = unsafe { MapVirtualKeyW(binding.hotkey.key.native(), MAPVK_VSC_TO_VK_EX) };
+            let registered = vk != 0 && unsafe { RegisterHotKey(null_mut(), id as i32, modifier_flags(binding.hotkey.modifiers), vk) } != 0;
+            if registered {
+                log_info!(App, "Registered hotkey {}", binding);
+            } else {
+                log_warn!(App, "Unable to register hotkey {}: {}", binding.hotkey, io::Error::last_os_error());
+            }
+        }
+
+        let mut message: MSG = unsafe { std::mem::zeroed() };
+        while unsafe { GetMessageW(&mut message, null_mut(), 0, 0) } > 0 {
+            if message.message != WM_HOTKEY {
+                continue;
+            }
+            if let Some(binding) = bindings.get(message.wParam) {
+                if commands.send(binding.command).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    Ok(())
+}
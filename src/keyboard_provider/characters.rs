@@ -0,0 +1,85 @@
+// Types the characters key events were looked up for instead of their keys, for sites that read
+// KeyboardEvent.key rather than physical keys. Backends that can inject characters directly (SendInput,
+// CGEvent) do so, the others get the keys that type the character on the layout the OS uses.
+
+use std::io;
+
+use super::{KeyboardBackend, RecordedEvents};
+use crate::keycodes::{KeyEvent, KeyEvents, Layout};
+
+enum Typed {
+    Keys(KeyEvents),
+    Char(char, bool),
+}
+
+pub struct CharacterKeyboard {
+    inner: Box<dyn KeyboardBackend>,
+    layout: Layout,
+}
+
+impl CharacterKeyboard {
+    pub fn new(inner: Box<dyn KeyboardBackend>, layout: Layout) -> Self {
+        CharacterKeyboard { inner, layout }
+    }
+
+    fn typed(&self, event: KeyEvent) -> Typed {
+        let key = event.key();
+        let press = matches!(event, KeyEvent::Press(_));
+
+        match key.ch {
+            None => Typed::Keys(vec![event]),
+            Some(_) if key.is_modifier() => Typed::Keys(vec![]), // only held to type the character
+            Some(c) if self.inner.types_characters() => Typed::Char(c, press),
+            Some(c) => match self.layout.key_for_char(c) {
+                Some(layout_key) if press => Typed::Keys(layout_key.press_with_modifiers()),
+                Some(layout_key) => Typed::Keys(vec![KeyEvent::Release(layout_key)]),
+                None => {
+                    log_warn!(KeyOut, "[{}]: {} has no key for {:?}", self.name(), self.layout.name(), c);
+                    Typed::Keys(vec![])
+                }
+            },
+        }
+    }
+}
+
+impl KeyboardBackend for CharacterKeyboard {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn write_code(&mut self, event: KeyEvent) -> Result<(), io::Error> {
+        match self.typed(event) {
+            Typed::Keys(events) => events.into_iter().try_for_each(|event| self.inner.write_code(event)),
+            Typed::Char(c, press) => self.inner.write_char(c, press),
+        }
+    }
+
+    // Keeps runs of plain keys together so the inner backend can still batch them
+    fn write_many(&mut self, events: KeyEvents) {
+        let mut keys = vec![];
+        for event in events {
+            match self.typed(event) {
+                Typed::Keys(mut translated) => keys.append(&mut translated),
+                Typed::Char(c, press) => {
+                    if !keys.is_empty() {
+                        self.inner.write_many(std::mem::take(&mut keys));
+                    }
+                    if let Err(err) = self.inner.write_char(c, press) {
+                        log_error!(KeyOut, "[{}]: Failed to type {:?}: {}", self.name(), c, err);
+                    }
+                }
+            }
+        }
+        if !keys.is_empty() {
+            self.inner.write_many(keys);
+        }
+    }
+
+    fn recorded_events(&self) -> Option<RecordedEvents> {
+        self.inner.recorded_events()
+    }
+
+    fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+    }
+}
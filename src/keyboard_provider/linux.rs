@@ -1,48 +1,139 @@
 use evdev::*;
 use std::io;
 
-use super::VirtualKeyboard;
+use super::{BackendKind, KeyboardBackend, VirtualKeyboard};
 
-use crate::keycodes::{KeyEvent, KeyEvents, KeypressType, UniversalKeyCode};
+use crate::keycodes::{KeyEvent, KeyEvents, KeypressType};
+use crate::output_methods::possible_keys;
+
+// Arbitrary ids so the device can be told apart from real keyboards, e.g. for blacklisting it
+const VENDOR_ID: u16 = 0x6d71;
+const PRODUCT_ID: u16 = 0x7771;
 
 impl VirtualKeyboard {
-    pub fn new() -> Result<Self, io::Error> {
-        let keys = evdev::AttributeSet::from_iter((0..560).map(evdev::Key));
+    pub fn new(name: &str) -> Result<Self, io::Error> {
+        // Only the keys we can ever send, so desktops don't mistake the device for a full keyboard
+        let keys = evdev::AttributeSet::from_iter(possible_keys().into_iter().map(|id| evdev::Key(id.native() as u16)));
 
         let device = uinput::VirtualDeviceBuilder::new()?
-            .name("miditoqwerty")
-            .input_id(evdev::InputId::new(evdev::BusType::BUS_I8042, 1, 1, 1))
-            .with_keys(&keys);
+            .name(name)
+            .input_id(evdev::InputId::new(evdev::BusType::BUS_VIRTUAL, VENDOR_ID, PRODUCT_ID, 1))
+            .with_keys(&keys)?;
 
-        let mut device = device.expect("Failed to build device").build()?;
+        let mut device = device.build()?;
         let devnode = device
-            .enumerate_dev_nodes_blocking().unwrap()
+            .enumerate_dev_nodes_blocking()?
             .next() // Expect only one. Using fold or calling next again blocks indefinitely
             .ok_or_else(|| io::Error::new(std::io::ErrorKind::NotFound, "devnode is not found"))??;
-        println!("Created device {:#?}", devnode);
+        log_info!(App, "Created device \"{}\" at {:?}", name, devnode);
+
+        Ok(VirtualKeyboard { device })
+    }
+}
 
-        Ok(VirtualKeyboard {
-            device,
-            raw_buf: vec![],
-        })
+impl KeyboardBackend for VirtualKeyboard {
+    fn name(&self) -> &'static str {
+        BackendKind::Native.name()
     }
 
-    pub fn write_code(&mut self, event: KeyEvent) -> Result<(), io::Error> {
+    fn write_code(&mut self, event: KeyEvent) -> Result<(), io::Error> {
         match event {
             KeyEvent::Press(keypress) => {
-                self.device.emit(&[InputEvent::new(EventType::KEY, keypress.code, KeypressType::Press as i32)]);
+                self.device.emit(&[InputEvent::new(EventType::KEY, keypress.code, KeypressType::Press as i32)])?;
             }
             KeyEvent::Release(keypress) => {
-                self.device.emit(&[InputEvent::new(EventType::KEY, keypress.code, KeypressType::Release as i32)]);
+                self.device.emit(&[InputEvent::new(EventType::KEY, keypress.code, KeypressType::Release as i32)])?;
             }
         }
 
         Ok(())
     }
 
-    pub fn write_many(&mut self, keypresses: KeyEvents) {
-        keypresses.iter().for_each(|event| {
-            self.write_code(event.clone());
-        })
+    fn write_many(&mut self, events: KeyEvents) {
+        // emit() ends every frame with a SYN_REPORT itself
+        let result = frames(&events).iter().try_for_each(|frame| self.device.emit(frame));
+        if let Err(err) = result {
+            log_error!(KeyOut, "[{}]: Failed to write {} events: {}", self.name(), events.len(), err);
+        }
+    }
+}
+
+// Splits the events into as few SYN_REPORT frames as possible. A key can only appear once per frame,
+// since a release and press of the same key in one frame may be merged or reordered by readers,
+// and modifiers get frames of their own so they're applied strictly before and after their key
+fn frames(events: &[KeyEvent]) -> Vec<Vec<InputEvent>> {
+    let mut frames = vec![];
+    let mut frame: Vec<InputEvent> = vec![];
+
+    for event in events {
+        let (key, value) = match event {
+            KeyEvent::Press(key) => (key, KeypressType::Press as i32),
+            KeyEvent::Release(key) => (key, KeypressType::Release as i32),
+        };
+        let modifier = key.is_modifier();
+
+        let repeated = frame.iter().any(|raw| raw.code() == key.code);
+        if (modifier || repeated) && !frame.is_empty() {
+            frames.push(std::mem::take(&mut frame));
+        }
+        frame.push(InputEvent::new(EventType::KEY, key.code, value));
+        if modifier {
+            frames.push(std::mem::take(&mut frame));
+        }
+    }
+    if !frame.is_empty() {
+        frames.push(frame);
+    }
+    frames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keycodes::Key;
+
+    fn grouped(events: &[KeyEvent]) -> Vec<Vec<(u16, i32)>> {
+        frames(events).iter().map(|frame| frame.iter().map(|raw| (raw.code(), raw.value())).collect()).collect()
+    }
+
+    fn code(s: &str) -> u16 {
+        Key::parse(s).unwrap().code
+    }
+
+    #[test]
+    fn pv_velocity_gets_a_frame_per_repeated_key() {
+        let alt = Key::parse("leftalt").unwrap();
+        let (velocity, t, u) = (Key::parse("8").unwrap(), Key::parse("t").unwrap(), Key::parse("u").unwrap());
+        let events = [
+            KeyEvent::Press(alt), KeyEvent::Release(velocity), KeyEvent::Press(velocity), KeyEvent::Release(velocity),
+            KeyEvent::Release(alt), KeyEvent::Release(t), KeyEvent::Press(t), KeyEvent::Press(u),
+        ];
+
+        let (press, release) = (KeypressType::Press as i32, KeypressType::Release as i32);
+        assert_eq!(grouped(&events), vec![
+            vec![(code("leftalt"), press)],
+            vec![(code("8"), release)],
+            vec![(code("8"), press)],
+            vec![(code("8"), release)],
+            vec![(code("leftalt"), release)],
+            vec![(code("t"), release)],
+            vec![(code("t"), press), (code("u"), press)],
+        ]);
+    }
+
+    #[test]
+    fn modifiers_get_frames_of_their_own() {
+        let mut events = vec![KeyEvent::Press(Key::parse("a").unwrap())];
+        events.extend(Key::parse("T").unwrap().press_with_modifiers());
+        events.push(KeyEvent::Press(Key::parse("b").unwrap()));
+
+        let press = KeypressType::Press as i32;
+        assert_eq!(grouped(&events), vec![
+            vec![(code("a"), press)],
+            vec![(code("shift"), press)],
+            vec![(code("t"), press)],
+            vec![(code("shift"), KeypressType::Release as i32)],
+            vec![(code("b"), press)],
+        ]);
     }
-}
\ No newline at end of file
+}
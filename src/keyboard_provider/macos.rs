@@ -7,19 +7,25 @@ use std::sync::{Arc, Mutex};
 
 use std::io;
 
-use super::VirtualKeyboard;
-use crate::keycodes::{KeyEvent, KeyEvents, KeypressType, Key};
+use super::{BackendKind, KeyboardBackend, VirtualKeyboard};
+use crate::keycodes::{KeyEvent, KeyEvents, KeypressType, KeyName};
 
 impl VirtualKeyboard {
     pub fn new() -> Result<Self, io::Error> {
         Ok(VirtualKeyboard { modifiers: (false, false, false) })
     }
+}
+
+impl KeyboardBackend for VirtualKeyboard {
+    fn name(&self) -> &'static str {
+        BackendKind::Native.name()
+    }
 
-    pub fn write_code(&mut self, event: KeyEvent) -> Result<(), io::Error> {
+    fn write_code(&mut self, event: KeyEvent) -> Result<(), io::Error> {
         //println!("mac event: {:?}", event);
         let source = CGEventSource::new(CGEventSourceStateID::HIDSystemState).unwrap();
 
-        if event.code() == Key::new("shift").code { // shift
+        if matches!(event.key().id, KeyName::Shift | KeyName::RightShift) { // shift
             match event {
                 KeyEvent::Press(keypress) => {
                     self.modifiers.0 = true;
@@ -32,7 +38,7 @@ impl VirtualKeyboard {
             //println!("shifting: {:?}", self.modifiers);
             // return Ok(());
         }
-        if event.code() == Key::new("leftalt").code { // alt
+        if matches!(event.key().id, KeyName::LeftAlt | KeyName::RightAlt) { // alt
             match event {
                 KeyEvent::Press(keypress) => {
                     self.modifiers.1 = true;
@@ -45,7 +51,7 @@ impl VirtualKeyboard {
             //println!("alting: {:?}", self.modifiers);
             // return Ok(());
         }
-        if event.code() == Key::new("leftctrl").code { // ctrl
+        if matches!(event.key().id, KeyName::LeftCtrl | KeyName::RightCtrl) { // ctrl
             match event {
                 KeyEvent::Press(keypress) => {
                     self.modifiers.2 = true;
@@ -89,9 +95,22 @@ impl VirtualKeyboard {
         Ok(())
     }
 
-    pub fn write_many(&mut self, keypresses: KeyEvents) {
-        keypresses.iter().for_each(|event| {
-            self.write_code(event.clone());
-        })
+    fn types_characters(&self) -> bool {
+        true
+    }
+
+    // The keycode doesn't matter once the event carries a string, apps read the string instead
+    fn write_char(&mut self, c: char, press: bool) -> Result<(), io::Error> {
+        let source = CGEventSource::new(CGEventSourceStateID::HIDSystemState)
+            .map_err(|_| io::Error::other("Unable to create a CGEventSource"))?;
+        let cgevent = CGEvent::new_keyboard_event(source, 0, press)
+            .map_err(|_| io::Error::other("Unable to create a keyboard CGEvent"))?;
+
+        let mut buf = [0u16; 2];
+        cgevent.set_string_from_utf16_unchecked(c.encode_utf16(&mut buf));
+        cgevent.set_flags(CGEventFlags::empty());
+        cgevent.post(CGEventTapLocation::HID);
+
+        Ok(())
     }
 }
@@ -1,10 +1,11 @@
 #![allow(dead_code)]
 #![allow(unused)]
 
-use std::path::Display;
+use std::io;
+use std::path::{Display, PathBuf};
 
 #[cfg(target_os = "linux")]
-use evdev::{uinput, InputEvent};
+use evdev::uinput;
 
 use std::sync::{Arc, Mutex};
 
@@ -15,15 +16,33 @@ struct KeyboardProvider {}
 
 #[cfg(target_os = "linux")]
 mod linux;
+#[cfg(target_os = "linux")]
+mod xtest;
+#[cfg(target_os = "linux")]
+mod wayland;
+#[cfg(target_os = "linux")]
+mod uinput_diagnostics;
 #[cfg(target_os = "windows")]
 mod windows;
 #[cfg(target_os = "macos")]
 mod macos;
+mod recorder;
+mod network;
+mod characters;
+
+pub use recorder::{RecordedEvent, RecordedEvents, RecordingKeyboard};
+pub use network::{NetworkKeyboard, serve_keys};
+pub use characters::CharacterKeyboard;
+#[cfg(target_os = "linux")]
+pub use xtest::XTestKeyboard;
+#[cfg(target_os = "linux")]
+pub use wayland::WaylandKeyboard;
+
+use crate::keycodes::{KeyEvent, KeyEvents, Layout};
 
 #[cfg(target_os = "linux")]
 pub struct VirtualKeyboard {
     device: uinput::VirtualDevice,
-    raw_buf: Vec<InputEvent>,
 }
 
 #[cfg(target_os = "windows")]
@@ -42,6 +61,196 @@ impl std::fmt::Debug for VirtualKeyboard {
     }
 }
 
-pub fn create_virtual_keyboard() -> VirtualKeyboard {
-    VirtualKeyboard::new().unwrap()
+// Anything key events can be sent to. Implementations are picked at runtime, see BackendKind
+pub trait KeyboardBackend: Send {
+    fn name(&self) -> &'static str;
+    fn write_code(&mut self, event: KeyEvent) -> Result<(), io::Error>;
+
+    fn write_many(&mut self, events: KeyEvents) {
+        events.iter().for_each(|event| {
+            if let Err(err) = self.write_code(event.clone()) {
+                log_error!(KeyOut, "[{}]: Failed to write {:?}: {}", self.name(), event, err);
+            }
+        })
+    }
+
+    // Backends that can type any character without going through the keyboard layout
+    fn types_characters(&self) -> bool {
+        false
+    }
+    fn write_char(&mut self, c: char, press: bool) -> Result<(), io::Error> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, format!("{} can't type characters", self.name())))
+    }
+
+    // Only the dry-run recorder has anything to show here
+    fn recorded_events(&self) -> Option<RecordedEvents> {
+        None
+    }
+
+    // Only matters when typing characters through their keys
+    fn set_layout(&mut self, layout: Layout) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    Native,
+    Wayland, // Linux only
+    XTest, // Linux only
+    DryRun,
+    Network,
+}
+
+impl BackendKind {
+    #[cfg(target_os = "linux")]
+    pub const ALL: &'static [BackendKind] = &[BackendKind::Native, BackendKind::Wayland, BackendKind::XTest, BackendKind::DryRun, BackendKind::Network];
+    #[cfg(not(target_os = "linux"))]
+    pub const ALL: &'static [BackendKind] = &[BackendKind::Native, BackendKind::DryRun, BackendKind::Network];
+
+    // Tried in order when nothing was chosen, the first one that can be created wins
+    #[cfg(target_os = "linux")]
+    pub const DEFAULTS: &'static [BackendKind] = &[BackendKind::Native, BackendKind::Wayland, BackendKind::XTest];
+    #[cfg(not(target_os = "linux"))]
+    pub const DEFAULTS: &'static [BackendKind] = &[BackendKind::Native];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BackendKind::Native => NATIVE_NAME,
+            BackendKind::Wayland => "Wayland",
+            BackendKind::XTest => "X11 XTest",
+            BackendKind::DryRun => "Dry run",
+            BackendKind::Network => "Network",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            BackendKind::Native => "Sends keys to the focused window",
+            BackendKind::Wayland => "Sends keys through the compositor's virtual keyboard protocol (wlroots), no /dev/uinput access needed",
+            BackendKind::XTest => "Sends keys through the X server, no /dev/uinput access needed",
+            BackendKind::DryRun => "Records key events instead of sending them",
+            BackendKind::Network => "Forwards key events to another machine running --key-server",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<BackendKind> {
+        let kind = match s.to_ascii_lowercase().as_str() {
+            "native" => BackendKind::Native,
+            "wayland" => BackendKind::Wayland,
+            "xtest" => BackendKind::XTest,
+            "dry-run" => BackendKind::DryRun,
+            "network" => BackendKind::Network,
+            _ => return None,
+        };
+        BackendKind::ALL.contains(&kind).then_some(kind)
+    }
+}
+
+#[cfg(target_os = "linux")]
+const NATIVE_NAME: &str = "uinput";
+#[cfg(target_os = "windows")]
+const NATIVE_NAME: &str = "SendInput";
+#[cfg(target_os = "macos")]
+const NATIVE_NAME: &str = "CGEvent";
+
+pub const DEFAULT_DEVICE_NAME: &str = "miditoqwerty";
+
+#[derive(Debug, Clone)]
+pub struct BackendOptions {
+    pub device_name: String, // uinput device name, so it can be recognized or blacklisted elsewhere
+    pub dry_run_file: Option<PathBuf>,
+    pub network_address: Option<String>,
+    pub type_characters: bool, // see CharacterKeyboard
+    pub layout: Layout,
+}
+impl Default for BackendOptions {
+    fn default() -> Self {
+        BackendOptions {
+            device_name: DEFAULT_DEVICE_NAME.to_owned(),
+            dry_run_file: None,
+            network_address: None,
+            type_characters: false,
+            layout: Layout::default(),
+        }
+    }
+}
+
+pub fn create_backend(kind: BackendKind, options: &BackendOptions) -> Result<Box<dyn KeyboardBackend>, io::Error> {
+    let backend: Box<dyn KeyboardBackend> = match kind {
+        #[cfg(target_os = "linux")]
+        BackendKind::Native => Box::new(VirtualKeyboard::new(&options.device_name)?),
+        #[cfg(not(target_os = "linux"))]
+        BackendKind::Native => Box::new(VirtualKeyboard::new()?),
+        #[cfg(target_os = "linux")]
+        BackendKind::Wayland => Box::new(WaylandKeyboard::new()?),
+        #[cfg(target_os = "linux")]
+        BackendKind::XTest => Box::new(XTestKeyboard::new()?),
+        #[cfg(not(target_os = "linux"))]
+        BackendKind::Wayland | BackendKind::XTest => {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, format!("{} is only available on Linux", kind.name())))
+        }
+        BackendKind::DryRun => {
+            let recorder = RecordingKeyboard::new();
+            match &options.dry_run_file {
+                Some(path) => Box::new(recorder.with_file(path)?),
+                None => Box::new(recorder),
+            }
+        }
+        BackendKind::Network => {
+            let address = options.network_address.as_deref()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No address to forward key events to"))?;
+            Box::new(NetworkKeyboard::connect(address)?)
+        }
+    };
+    log_info!(App, "Using the {} keyboard backend", backend.name());
+
+    if !options.type_characters {
+        return Ok(backend);
+    }
+    // The Wayland keyboard brings its own US keymap, whatever the OS layout is
+    let layout = match kind {
+        BackendKind::Wayland => Layout::Us,
+        _ => options.layout,
+    };
+    log_info!(App, "Typing characters through the {} keyboard", backend.name());
+    Ok(Box::new(CharacterKeyboard::new(backend, layout)))
+}
+
+// Creates the first of BackendKind::DEFAULTS that works, or explains why none did
+pub fn create_default_backend(options: &BackendOptions) -> Result<(BackendKind, Box<dyn KeyboardBackend>), String> {
+    let mut failures = vec![];
+
+    for &kind in BackendKind::DEFAULTS {
+        match create_backend(kind, options) {
+            Ok(backend) => return Ok((kind, backend)),
+            Err(err) => {
+                log_warn!(App, "The {} keyboard is unavailable: {}", kind.name(), err);
+                failures.push(format!("{}: {}", kind.name(), err));
+            }
+        }
+    }
+
+    let mut message = format!("No way to send keys was found.\n\n{}", failures.join("\n"));
+    if let Some(problem) = native_problem() {
+        message.push_str(&format!("\n\n{}", problem));
+    }
+    Err(message)
+}
+
+// Explains why the native keyboard can't be created, if we know how to tell
+#[cfg(target_os = "linux")]
+pub fn native_problem() -> Option<String> {
+    uinput_diagnostics::diagnose().map(|problem| problem.to_string())
+}
+#[cfg(not(target_os = "linux"))]
+pub fn native_problem() -> Option<String> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+pub fn udev_setup() -> Option<String> {
+    Some(uinput_diagnostics::udev_setup())
+}
+#[cfg(not(target_os = "linux"))]
+pub fn udev_setup() -> Option<String> {
+    None
 }
@@ -0,0 +1,117 @@
+// Forwards key events to another machine as text lines ("press a", "release shift"), using
+// KeyName names so both ends can run on different platforms. serve_keys is the receiving end.
+
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::{BackendKind, KeyboardBackend};
+use crate::keycodes::{Key, KeyEvent, KeyEvents, KeyName};
+
+// Connecting happens on the window's thread when switching outputs, so it can't hang there for long
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct NetworkKeyboard {
+    stream: BufWriter<TcpStream>,
+}
+
+impl NetworkKeyboard {
+    pub fn connect(address: &str) -> Result<Self, io::Error> {
+        let mut last_err = io::Error::new(io::ErrorKind::InvalidInput, format!("{address} doesn't resolve to an address"));
+        let stream = address.to_socket_addrs()?
+            .find_map(|addr| TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT).map_err(|err| last_err = err).ok())
+            .ok_or(last_err)?;
+        stream.set_nodelay(true)?;
+        log_info!(App, "Forwarding key events to {}", stream.peer_addr()?);
+        Ok(NetworkKeyboard { stream: BufWriter::new(stream) })
+    }
+}
+
+impl KeyboardBackend for NetworkKeyboard {
+    fn name(&self) -> &'static str {
+        BackendKind::Network.name()
+    }
+
+    fn write_code(&mut self, event: KeyEvent) -> Result<(), io::Error> {
+        writeln!(self.stream, "{}", event)?;
+        self.stream.flush()
+    }
+
+    // One flush (and usually one packet) per MIDI message instead of per key
+    fn write_many(&mut self, events: KeyEvents) {
+        let result = events.iter()
+            .try_for_each(|event| writeln!(self.stream, "{}", event))
+            .and_then(|_| self.stream.flush());
+        if let Err(err) = result {
+            log_error!(KeyOut, "[{}]: Failed to forward {} events: {}", self.name(), events.len(), err);
+        }
+    }
+}
+
+fn parse_line(line: &str) -> Option<KeyEvent> {
+    let (action, name) = line.trim().split_once(' ')?;
+    let key = Key::from(KeyName::from_name(name)?);
+
+    match action {
+        "press" => Some(KeyEvent::Press(key)),
+        "release" => Some(KeyEvent::Release(key)),
+        _ => None,
+    }
+}
+
+fn handle_client(stream: TcpStream, backend: Arc<Mutex<Box<dyn KeyboardBackend>>>) {
+    let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or("unknown".to_owned());
+    log_info!(App, "Key events connection from {}", peer);
+
+    let mut held: Vec<Key> = vec![];
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                log_warn!(App, "Key events connection from {} failed: {}", peer, err);
+                break;
+            }
+        };
+
+        let event = match parse_line(&line) {
+            Some(event) => event,
+            None => {
+                log_warn!(KeyOut, "Ignoring invalid key event from {}: {:?}", peer, line);
+                continue;
+            }
+        };
+
+        match &event {
+            KeyEvent::Press(key) => held.push(*key),
+            KeyEvent::Release(key) => held.retain(|held_key| held_key.code != key.code),
+        }
+        if let Err(err) = backend.lock().unwrap().write_code(event) {
+            log_error!(KeyOut, "Failed to write forwarded key event: {}", err);
+        }
+    }
+
+    // Don't leave keys stuck down when the sender goes away mid-song
+    backend.lock().unwrap().write_many(held.into_iter().map(KeyEvent::Release).collect());
+    log_info!(App, "Key events connection from {} closed", peer);
+}
+
+// Receives forwarded key events and writes them to `backend`, blocking forever
+pub fn serve_keys(address: &str, backend: Box<dyn KeyboardBackend>) -> Result<(), io::Error> {
+    let listener = TcpListener::bind(address)?;
+    log_info!(App, "Waiting for forwarded key events on {}", listener.local_addr()?);
+
+    let backend = Arc::new(Mutex::new(backend));
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let backend = Arc::clone(&backend);
+                thread::spawn(move || handle_client(stream, backend));
+            }
+            Err(err) => log_warn!(App, "Failed to accept key events connection: {}", err),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::{BackendKind, KeyboardBackend};
+use crate::keycodes::{KeyEvent, KeyEvents};
+
+const MAX_RECORDED: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedEvent {
+    pub time: Duration, // since the recorder was created
+    pub event: KeyEvent,
+}
+impl std::fmt::Display for RecordedEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:>9.3} {}", self.time.as_secs_f64(), self.event)
+    }
+}
+
+pub type RecordedEvents = Arc<Mutex<VecDeque<RecordedEvent>>>;
+
+// Collects key events instead of injecting them, for trying mappings without a game
+// and for running the output methods where no real keyboard can be created
+pub struct RecordingKeyboard {
+    started: Instant,
+    events: RecordedEvents,
+    file: Option<BufWriter<File>>,
+}
+
+impl RecordingKeyboard {
+    pub fn new() -> Self {
+        RecordingKeyboard {
+            started: Instant::now(),
+            events: Arc::new(Mutex::new(VecDeque::new())),
+            file: None,
+        }
+    }
+
+    pub fn with_file(mut self, path: &Path) -> Result<Self, io::Error> {
+        self.file = Some(BufWriter::new(File::create(path)?));
+        Ok(self)
+    }
+
+    // Shared view of the recorded events, for the GUI
+    pub fn events(&self) -> RecordedEvents {
+        Arc::clone(&self.events)
+    }
+
+    pub fn take(&mut self) -> Vec<RecordedEvent> {
+        self.events.lock().unwrap().drain(..).collect()
+    }
+
+    // Leaves flushing the file to the caller, once for all the events written together
+    fn record(&mut self, event: KeyEvent) -> Result<(), io::Error> {
+        let recorded = RecordedEvent { time: self.started.elapsed(), event };
+
+        if let Some(file) = self.file.as_mut() {
+            writeln!(file, "{}", recorded)?;
+        }
+
+        let mut events = self.events.lock().unwrap();
+        if events.len() == MAX_RECORDED {
+            events.pop_front();
+        }
+        events.push_back(recorded);
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.file.as_mut().map_or(Ok(()), |file| file.flush())
+    }
+}
+
+impl KeyboardBackend for RecordingKeyboard {
+    fn name(&self) -> &'static str {
+        BackendKind::DryRun.name()
+    }
+
+    fn write_code(&mut self, event: KeyEvent) -> Result<(), io::Error> {
+        self.record(event)?;
+        self.flush()
+    }
+
+    fn write_many(&mut self, events: KeyEvents) {
+        for event in events.iter() {
+            if let Err(err) = self.record(event.clone()) {
+                log_error!(KeyOut, "[{}]: Failed to write {:?}: {}", self.name(), event, err);
+            }
+        }
+        if let Err(err) = self.flush() {
+            log_error!(KeyOut, "[{}]: Failed to write the recorded events: {}", self.name(), err);
+        }
+    }
+
+    fn recorded_events(&self) -> Option<RecordedEvents> {
+        Some(self.events())
+    }
+}
@@ -0,0 +1,87 @@
+// Figures out why /dev/uinput can't be used, so users get instructions instead of a panic
+
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+
+const UINPUT_PATHS: [&str; 2] = ["/dev/uinput", "/dev/input/uinput"];
+const RULES_FILE: &str = "/etc/udev/rules.d/60-miditoqwerty-uinput.rules";
+const UDEV_RULE: &str = r#"KERNEL=="uinput", SUBSYSTEM=="misc", GROUP="input", MODE="0660", OPTIONS+="static_node=uinput""#;
+
+#[derive(Debug)]
+pub enum UinputProblem {
+    ModuleNotLoaded,
+    MissingDevice, // module is loaded, but udev never created the node
+    PermissionDenied { path: String, group: String, mode: u32 },
+    Other { path: String, error: io::Error },
+}
+
+impl std::fmt::Display for UinputProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UinputProblem::ModuleNotLoaded => write!(
+                f, "The uinput kernel module isn't loaded, so /dev/uinput doesn't exist.\n\
+                Load it with `sudo modprobe uinput`, see `--print-udev-rule` to load it on every boot."
+            ),
+            UinputProblem::MissingDevice => write!(
+                f, "The uinput module is loaded, but /dev/uinput doesn't exist.\n\
+                Check that udev is running, or create it with `sudo mknod /dev/uinput c 10 223`."
+            ),
+            UinputProblem::PermissionDenied { path, group, mode } => write!(
+                f, "{path} belongs to group \"{group}\" with permissions {mode:o}, and this user can't write to it.\n\
+                Run `miditoqwerty-rs --print-udev-rule` for the udev rule and group setup that allows it without sudo."
+            ),
+            UinputProblem::Other { path, error } => write!(f, "{path} can't be opened: {error}"),
+        }
+    }
+}
+
+fn group_name(gid: u32) -> String {
+    fs::read_to_string("/etc/group").ok()
+        .and_then(|groups| groups.lines().find_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let id = fields.nth(1)?.parse::<u32>().ok()?;
+            (id == gid).then(|| name.to_owned())
+        }))
+        .unwrap_or_else(|| gid.to_string())
+}
+
+pub fn diagnose() -> Option<UinputProblem> {
+    let Some(path) = UINPUT_PATHS.into_iter().find(|path| Path::new(path).exists()) else {
+        return Some(match Path::new("/sys/module/uinput").exists() {
+            true => UinputProblem::MissingDevice,
+            false => UinputProblem::ModuleNotLoaded,
+        });
+    };
+
+    match OpenOptions::new().write(true).open(path) {
+        Ok(_) => None,
+        Err(error) if error.kind() == io::ErrorKind::PermissionDenied => {
+            let metadata = fs::metadata(path).ok()?;
+            Some(UinputProblem::PermissionDenied {
+                path: path.to_owned(),
+                group: group_name(metadata.gid()),
+                mode: metadata.permissions().mode() & 0o777,
+            })
+        }
+        Err(error) => Some(UinputProblem::Other { path: path.to_owned(), error }),
+    }
+}
+
+// Printed by --print-udev-rule, valid as a rules file on its own
+pub fn udev_setup() -> String {
+    format!(
+        "# Lets members of the \"input\" group use /dev/uinput, so miditoqwerty-rs runs without sudo.\n\
+        #\n\
+        # Setup (log out and back in afterwards for the group change to apply):\n\
+        #   miditoqwerty-rs --print-udev-rule | sudo tee {RULES_FILE}\n\
+        #   echo uinput | sudo tee /etc/modules-load.d/uinput.conf\n\
+        #   sudo modprobe uinput\n\
+        #   sudo groupadd -f input\n\
+        #   sudo usermod -aG input \"$USER\"\n\
+        #   sudo udevadm control --reload-rules && sudo udevadm trigger\n\
+        {UDEV_RULE}\n"
+    )
+}
@@ -0,0 +1,385 @@
+// Types through the zwp_virtual_keyboard_v1 protocol, supported by wlroots-based compositors,
+// so no /dev/uinput access is needed. The compositor only knows the keys we describe in our
+// own XKB keymap, which is generated from KeyName, and modifiers have to be sent explicitly.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::AsFd;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::{wl_keyboard, wl_registry, wl_seat};
+use wayland_client::{delegate_noop, Connection, Dispatch, EventQueue, QueueHandle};
+use wayland_protocols_misc::zwp_virtual_keyboard_v1::client::{
+    zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1, zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1,
+};
+
+use super::{BackendKind, KeyboardBackend};
+use crate::keycodes::{KeyEvent, KeyEvents, KeyName};
+
+// X keycodes are evdev keycodes shifted by 8
+const EVDEV_OFFSET: u32 = 8;
+
+// Modifier masks of the standard "complete" XKB compatibility map
+const SHIFT_MASK: u32 = 1 << 0;
+const CONTROL_MASK: u32 = 1 << 2;
+const ALT_MASK: u32 = 1 << 3;
+const SUPER_MASK: u32 = 1 << 6;
+
+struct State;
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
+    fn event(_: &mut Self, _: &wl_registry::WlRegistry, _: wl_registry::Event, _: &GlobalListContents, _: &Connection, _: &QueueHandle<Self>) {}
+}
+delegate_noop!(State: ignore wl_seat::WlSeat);
+delegate_noop!(State: ZwpVirtualKeyboardManagerV1);
+delegate_noop!(State: ZwpVirtualKeyboardV1);
+
+fn wayland_error(err: impl std::fmt::Display) -> io::Error {
+    io::Error::other(format!("Wayland: {err}"))
+}
+
+// XKB keysyms for the unshifted and shifted level of each KeyName name
+fn keysyms(name: &str) -> Option<(&'static str, Option<&'static str>)> {
+    let syms = match name {
+        "1" => ("1", Some("exclam")),
+        "2" => ("2", Some("at")),
+        "3" => ("3", Some("numbersign")),
+        "4" => ("4", Some("dollar")),
+        "5" => ("5", Some("percent")),
+        "6" => ("6", Some("asciicircum")),
+        "7" => ("7", Some("ampersand")),
+        "8" => ("8", Some("asterisk")),
+        "9" => ("9", Some("parenleft")),
+        "0" => ("0", Some("parenright")),
+        "minus" => ("minus", Some("underscore")),
+        "equal" => ("equal", Some("plus")),
+        "leftbrace" => ("bracketleft", Some("braceleft")),
+        "rightbrace" => ("bracketright", Some("braceright")),
+        "semicolon" => ("semicolon", Some("colon")),
+        "apostrophe" => ("apostrophe", Some("quotedbl")),
+        "grave" => ("grave", Some("asciitilde")),
+        "backslash" => ("backslash", Some("bar")),
+        "102nd" => ("less", Some("greater")),
+        "comma" => ("comma", Some("less")),
+        "dot" => ("period", Some("greater")),
+        "slash" => ("slash", Some("question")),
+        "esc" => ("Escape", None),
+        "backspace" => ("BackSpace", None),
+        "tab" => ("Tab", None),
+        "enter" => ("Return", None),
+        "space" => ("space", None),
+        "capslock" => ("Caps_Lock", None),
+        "numlock" => ("Num_Lock", None),
+        "scrolllock" => ("Scroll_Lock", None),
+        "shift" => ("Shift_L", None),
+        "rightshift" => ("Shift_R", None),
+        "leftctrl" => ("Control_L", None),
+        "rightctrl" => ("Control_R", None),
+        "leftalt" => ("Alt_L", None),
+        "rightalt" => ("Alt_R", None),
+        "leftmeta" => ("Super_L", None),
+        "rightmeta" => ("Super_R", None),
+        "f1" => ("F1", None),
+        "f2" => ("F2", None),
+        "f3" => ("F3", None),
+        "f4" => ("F4", None),
+        "f5" => ("F5", None),
+        "f6" => ("F6", None),
+        "f7" => ("F7", None),
+        "f8" => ("F8", None),
+        "f9" => ("F9", None),
+        "f10" => ("F10", None),
+        "f11" => ("F11", None),
+        "f12" => ("F12", None),
+        "home" => ("Home", None),
+        "end" => ("End", None),
+        "pageup" => ("Prior", None),
+        "pagedown" => ("Next", None),
+        "insert" => ("Insert", None),
+        "delete" => ("Delete", None),
+        "up" => ("Up", None),
+        "down" => ("Down", None),
+        "left" => ("Left", None),
+        "right" => ("Right", None),
+        "kpslash" => ("KP_Divide", None),
+        "kpenter" => ("KP_Enter", None),
+        "kpdot" => ("KP_Decimal", None),
+        "kpasterisk" => ("KP_Multiply", None),
+        "kpminus" => ("KP_Subtract", None),
+        "kpplus" => ("KP_Add", None),
+        "kp0" => ("KP_0", None),
+        "kp1" => ("KP_1", None),
+        "kp2" => ("KP_2", None),
+        "kp3" => ("KP_3", None),
+        "kp4" => ("KP_4", None),
+        "kp5" => ("KP_5", None),
+        "kp6" => ("KP_6", None),
+        "kp7" => ("KP_7", None),
+        "kp8" => ("KP_8", None),
+        "kp9" => ("KP_9", None),
+        letter if letter.len() == 1 && letter.as_bytes()[0].is_ascii_lowercase() => {
+            // Keysym names of letters are the letters themselves
+            let index = (letter.as_bytes()[0] - b'a') as usize;
+            const LOWER: [&str; 26] = ["a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", "p", "q", "r", "s", "t", "u", "v", "w", "x", "y", "z"];
+            const UPPER: [&str; 26] = ["A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", "P", "Q", "R", "S", "T", "U", "V", "W", "X", "Y", "Z"];
+            (LOWER[index], Some(UPPER[index]))
+        }
+        _ => return None,
+    };
+    Some(syms)
+}
+
+fn modifier_mask(name: &str) -> u32 {
+    match name {
+        "shift" | "rightshift" => SHIFT_MASK,
+        "leftctrl" | "rightctrl" => CONTROL_MASK,
+        "leftalt" | "rightalt" => ALT_MASK,
+        "leftmeta" | "rightmeta" => SUPER_MASK,
+        _ => 0,
+    }
+}
+
+pub fn generate_keymap() -> String {
+    let mut keys: Vec<(&str, u32)> = KeyName::ALL.iter().map(|id| (id.name(), id.native())).collect();
+    keys.sort_by_key(|(_, code)| *code);
+
+    let mut keycodes = String::new();
+    let mut symbols = String::new();
+    let mut modifier_maps = String::new();
+    for (name, code) in keys {
+        let Some((lower, upper)) = keysyms(name) else {
+            log_warn!(App, "[Wayland]: No keysym for {}, leaving it out of the keymap", name);
+            continue;
+        };
+
+        keycodes.push_str(&format!("        <K{code}> = {};\n", code + EVDEV_OFFSET));
+        match upper {
+            Some(upper) => symbols.push_str(&format!("        key <K{code}> {{ [ {lower}, {upper} ] }};\n")),
+            None => symbols.push_str(&format!("        key <K{code}> {{ [ {lower} ] }};\n")),
+        }
+        match modifier_mask(name) {
+            SHIFT_MASK => modifier_maps.push_str(&format!("        modifier_map Shift {{ <K{code}> }};\n")),
+            CONTROL_MASK => modifier_maps.push_str(&format!("        modifier_map Control {{ <K{code}> }};\n")),
+            ALT_MASK => modifier_maps.push_str(&format!("        modifier_map Mod1 {{ <K{code}> }};\n")),
+            SUPER_MASK => modifier_maps.push_str(&format!("        modifier_map Mod4 {{ <K{code}> }};\n")),
+            _ => {}
+        }
+    }
+
+    format!(
+        "xkb_keymap {{\n    xkb_keycodes \"miditoqwerty\" {{\n        minimum = 8;\n        maximum = 255;\n{keycodes}    }};\n    \
+        xkb_types \"miditoqwerty\" {{ include \"complete\" }};\n    \
+        xkb_compatibility \"miditoqwerty\" {{ include \"complete\" }};\n    \
+        xkb_symbols \"miditoqwerty\" {{\n{symbols}{modifier_maps}    }};\n}};\n"
+    )
+}
+
+// The compositor wants the keymap as a file descriptor. The file is unlinked right away,
+// the open handle keeps it alive for as long as the compositor needs it
+fn keymap_file(keymap: &str) -> Result<File, io::Error> {
+    let dir = std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from).unwrap_or_else(std::env::temp_dir);
+    let path = dir.join(format!("miditoqwerty-keymap-{}", std::process::id()));
+
+    let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path)?;
+    fs::remove_file(&path)?;
+    file.write_all(keymap.as_bytes())?;
+    file.write_all(&[0])?; // XKB expects a NUL-terminated string
+    file.flush()?;
+    Ok(file)
+}
+
+pub struct WaylandKeyboard {
+    connection: Connection,
+    queue: EventQueue<State>,
+    keyboard: ZwpVirtualKeyboardV1,
+    _keymap: File,
+    modifiers: u32,
+    started: Instant,
+}
+
+impl WaylandKeyboard {
+    pub fn new() -> Result<Self, io::Error> {
+        let connection = Connection::connect_to_env().map_err(wayland_error)?;
+        let (globals, mut queue) = registry_queue_init::<State>(&connection).map_err(wayland_error)?;
+        let qh = queue.handle();
+
+        let seat: wl_seat::WlSeat = globals.bind(&qh, 1..=7, ()).map_err(wayland_error)?;
+        let manager: ZwpVirtualKeyboardManagerV1 = globals.bind(&qh, 1..=1, ())
+            .map_err(|_| io::Error::new(io::ErrorKind::Unsupported, "Wayland: the compositor doesn't support zwp_virtual_keyboard_v1"))?;
+        let keyboard = manager.create_virtual_keyboard(&seat, &qh, ());
+
+        let keymap = generate_keymap();
+        let keymap_file = keymap_file(&keymap)?;
+        keyboard.keymap(wl_keyboard::KeymapFormat::XkbV1 as u32, keymap_file.as_fd(), keymap.len() as u32 + 1);
+
+        // Surfaces protocol errors (e.g. being refused the keyboard) now rather than on the first note
+        queue.roundtrip(&mut State).map_err(wayland_error)?;
+        log_info!(App, "Created Wayland virtual keyboard");
+
+        Ok(WaylandKeyboard { connection, queue, keyboard, _keymap: keymap_file, modifiers: 0, started: Instant::now() })
+    }
+
+    fn send(&mut self, event: &KeyEvent) {
+        let (key, pressed) = match event {
+            KeyEvent::Press(key) => (key, true),
+            KeyEvent::Release(key) => (key, false),
+        };
+
+        let time = self.started.elapsed().as_millis() as u32;
+        self.keyboard.key(time, key.code as u32, pressed as u32);
+
+        let mask = modifier_mask(key.name());
+        if mask != 0 {
+            match pressed {
+                true => self.modifiers |= mask,
+                false => self.modifiers &= !mask,
+            }
+            self.keyboard.modifiers(self.modifiers, 0, 0, 0);
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.connection.flush().map_err(wayland_error)?;
+        // Nothing is expected back, but errors from the compositor would otherwise pile up unread
+        self.queue.dispatch_pending(&mut State).map_err(wayland_error)?;
+        Ok(())
+    }
+}
+
+impl KeyboardBackend for WaylandKeyboard {
+    fn name(&self) -> &'static str {
+        BackendKind::Wayland.name()
+    }
+
+    fn write_code(&mut self, event: KeyEvent) -> Result<(), io::Error> {
+        self.send(&event);
+        self.flush()
+    }
+
+    fn write_many(&mut self, events: KeyEvents) {
+        events.iter().for_each(|event| self.send(event));
+        if let Err(err) = self.flush() {
+            log_error!(KeyOut, "[{}]: Failed to write {} events: {}", self.name(), events.len(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use wayland_client::protocol::{wl_buffer, wl_compositor, wl_shm, wl_shm_pool, wl_surface};
+    use wayland_client::WEnum;
+    use wayland_protocols::xdg::shell::client::{xdg_surface, xdg_toplevel, xdg_wm_base};
+
+    use super::*;
+    use crate::keycodes::Key;
+
+    // A window of our own, to see the keys the compositor delivers to the focused client
+    #[derive(Default)]
+    struct Window {
+        configured: bool,
+        focused: bool,
+        keys: Vec<(u32, bool)>, // evdev code, pressed
+    }
+
+    impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for Window {
+        fn event(_: &mut Self, _: &wl_registry::WlRegistry, _: wl_registry::Event, _: &GlobalListContents, _: &Connection, _: &QueueHandle<Self>) {}
+    }
+    impl Dispatch<xdg_wm_base::XdgWmBase, ()> for Window {
+        fn event(_: &mut Self, base: &xdg_wm_base::XdgWmBase, event: xdg_wm_base::Event, _: &(), _: &Connection, _: &QueueHandle<Self>) {
+            if let xdg_wm_base::Event::Ping { serial } = event {
+                base.pong(serial);
+            }
+        }
+    }
+    impl Dispatch<xdg_surface::XdgSurface, ()> for Window {
+        fn event(window: &mut Self, surface: &xdg_surface::XdgSurface, event: xdg_surface::Event, _: &(), _: &Connection, _: &QueueHandle<Self>) {
+            if let xdg_surface::Event::Configure { serial } = event {
+                surface.ack_configure(serial);
+                window.configured = true;
+            }
+        }
+    }
+    impl Dispatch<wl_keyboard::WlKeyboard, ()> for Window {
+        fn event(window: &mut Self, _: &wl_keyboard::WlKeyboard, event: wl_keyboard::Event, _: &(), _: &Connection, _: &QueueHandle<Self>) {
+            match event {
+                wl_keyboard::Event::Enter { .. } => window.focused = true,
+                wl_keyboard::Event::Key { key, state, .. } => window.keys.push((key, state == WEnum::Value(wl_keyboard::KeyState::Pressed))),
+                _ => {}
+            }
+        }
+    }
+    delegate_noop!(Window: wl_compositor::WlCompositor);
+    delegate_noop!(Window: wl_shm_pool::WlShmPool);
+    delegate_noop!(Window: ignore wl_surface::WlSurface);
+    delegate_noop!(Window: ignore wl_shm::WlShm);
+    delegate_noop!(Window: ignore wl_buffer::WlBuffer);
+    delegate_noop!(Window: ignore wl_seat::WlSeat);
+    delegate_noop!(Window: ignore xdg_toplevel::XdgToplevel);
+
+    fn wait_for(queue: &mut EventQueue<Window>, window: &mut Window, done: impl Fn(&Window) -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !done(window) && Instant::now() < deadline {
+            queue.roundtrip(window).unwrap();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        done(window)
+    }
+
+    #[test]
+    fn keymap_covers_keycodes() {
+        let keymap = generate_keymap();
+        for id in KeyName::ALL {
+            assert!(keysyms(id.name()).is_some(), "No keysym for {}", id.name());
+            assert!(keymap.contains(&format!("<K{}> = {};", id.native(), id.native() + EVDEV_OFFSET)));
+        }
+    }
+
+    // Needs a compositor with zwp_virtual_keyboard_v1, e.g. `sway --headless` or `cage`
+    #[test]
+    #[ignore]
+    fn types_into_compositor() {
+        // First, so the seat has a keyboard for the window to get
+        let mut keyboard = WaylandKeyboard::new().expect("No compositor with zwp_virtual_keyboard_v1 available");
+
+        let connection = Connection::connect_to_env().unwrap();
+        let (globals, mut queue) = registry_queue_init::<Window>(&connection).unwrap();
+        let qh = queue.handle();
+        let compositor: wl_compositor::WlCompositor = globals.bind(&qh, 1..=6, ()).unwrap();
+        let shm: wl_shm::WlShm = globals.bind(&qh, 1..=1, ()).unwrap();
+        let base: xdg_wm_base::XdgWmBase = globals.bind(&qh, 1..=6, ()).unwrap();
+        let seat: wl_seat::WlSeat = globals.bind(&qh, 1..=7, ()).unwrap();
+        let _keyboard = seat.get_keyboard(&qh, ());
+
+        let surface = compositor.create_surface(&qh, ());
+        let xdg_surface = base.get_xdg_surface(&surface, &qh, ());
+        let _toplevel = xdg_surface.get_toplevel(&qh, ());
+        surface.commit();
+        let mut window = Window::default();
+        assert!(wait_for(&mut queue, &mut window, |window| window.configured), "The window was never configured");
+
+        // Shown once it has a buffer, a single pixel is enough
+        let path = std::env::temp_dir().join(format!("miditoqwerty-buffer-{}", std::process::id()));
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        file.set_len(4).unwrap();
+        let pool = shm.create_pool(file.as_fd(), 4, &qh, ());
+        let buffer = pool.create_buffer(0, 1, 1, 4, wl_shm::Format::Argb8888, &qh, ());
+        surface.attach(Some(&buffer), 0, 0);
+        surface.commit();
+        assert!(wait_for(&mut queue, &mut window, |window| window.focused), "The window never got keyboard focus");
+
+        let mut expected = vec![];
+        for name in ["shift", "a", "space"] {
+            let key = Key::parse(name).unwrap();
+            keyboard.write_code(KeyEvent::Press(key)).unwrap();
+            keyboard.write_code(KeyEvent::Release(key)).unwrap();
+            expected.extend([(key.code as u32, true), (key.code as u32, false)]);
+        }
+        wait_for(&mut queue, &mut window, |window| window.keys.len() >= expected.len());
+        assert_eq!(window.keys, expected);
+    }
+}
@@ -1,15 +1,20 @@
 extern crate winapi;
 use std::mem::size_of;
 use winapi::um::winuser::{
-    SendInput, INPUT, INPUT_KEYBOARD, KEYBDINPUT, KEYEVENTF_KEYUP, KEYEVENTF_SCANCODE, VK_RETURN
+    SendInput, INPUT, INPUT_KEYBOARD, KEYBDINPUT, KEYEVENTF_EXTENDEDKEY, KEYEVENTF_KEYUP, KEYEVENTF_SCANCODE, KEYEVENTF_UNICODE, VK_RETURN
 };
 
 use std::io;
 
-use super::VirtualKeyboard;
+use super::{BackendKind, KeyboardBackend, VirtualKeyboard};
 use crate::keycodes::{KeyEvent, KeyEvents, KeypressType};
 
+// Extended keys are stored as 0xe0__ in KeyName, SendInput wants the low byte plus a flag
+const EXTENDED_PREFIX: u16 = 0xe000;
+
 fn send_scancode(scancode: u16, status: KeypressType) {
+    let extended = scancode & 0xff00 == EXTENDED_PREFIX;
+
     let mut input = INPUT {
         type_: INPUT_KEYBOARD,
         u: unsafe { std::mem::zeroed() },
@@ -19,8 +24,32 @@ fn send_scancode(scancode: u16, status: KeypressType) {
     unsafe {
         *input.u.ki_mut() = KEYBDINPUT {
             wVk: 0,
-            wScan: scancode,
-            dwFlags: KEYEVENTF_SCANCODE | if matches!(status, KeypressType::Release) { KEYEVENTF_KEYUP } else { 0 },
+            wScan: scancode & 0xff,
+            dwFlags: KEYEVENTF_SCANCODE
+                | if extended { KEYEVENTF_EXTENDEDKEY } else { 0 }
+                | if matches!(status, KeypressType::Release) { KEYEVENTF_KEYUP } else { 0 },
+            time: 0,
+            dwExtraInfo: 0,
+        };
+    }
+
+    unsafe {
+        SendInput(1, &mut input, size_of::<INPUT>() as i32);
+    }
+}
+
+// Types one UTF-16 unit whatever the keyboard layout is, characters outside the BMP take two
+fn send_unicode(unit: u16, status: KeypressType) {
+    let mut input = INPUT {
+        type_: INPUT_KEYBOARD,
+        u: unsafe { std::mem::zeroed() },
+    };
+
+    unsafe {
+        *input.u.ki_mut() = KEYBDINPUT {
+            wVk: 0,
+            wScan: unit,
+            dwFlags: KEYEVENTF_UNICODE | if matches!(status, KeypressType::Release) { KEYEVENTF_KEYUP } else { 0 },
             time: 0,
             dwExtraInfo: 0,
         };
@@ -35,8 +64,14 @@ impl VirtualKeyboard {
     pub fn new() -> Result<Self, io::Error> {
         Ok(VirtualKeyboard {}) // Windows is simple, just one API call needed to send input
     }
+}
+
+impl KeyboardBackend for VirtualKeyboard {
+    fn name(&self) -> &'static str {
+        BackendKind::Native.name()
+    }
 
-    pub fn write_code(&mut self, event: KeyEvent) -> Result<(), io::Error> {
+    fn write_code(&mut self, event: KeyEvent) -> Result<(), io::Error> {
         match event {
             KeyEvent::Press(keypress) => {
                 send_scancode(keypress.code.try_into().unwrap(), KeypressType::Press);
@@ -48,9 +83,15 @@ impl VirtualKeyboard {
         Ok(())
     }
 
-    pub fn write_many(&mut self, events: KeyEvents) {
-        events.iter().for_each(|event| {
-            self.write_code(event.clone());
-        })
+    fn types_characters(&self) -> bool {
+        true
+    }
+
+    fn write_char(&mut self, c: char, press: bool) -> Result<(), io::Error> {
+        let mut buf = [0u16; 2];
+        for &unit in c.encode_utf16(&mut buf).iter() {
+            send_unicode(unit, if press { KeypressType::Press } else { KeypressType::Release });
+        }
+        Ok(())
     }
 }
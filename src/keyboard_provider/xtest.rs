@@ -0,0 +1,100 @@
+// Injects key events into the X server through the XTest extension, for X11 sessions
+// where /dev/uinput isn't writable. Works against Xvfb too, which makes it testable.
+
+use std::io;
+
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::protocol::xproto::{Window, KEY_PRESS_EVENT, KEY_RELEASE_EVENT};
+use x11rb::protocol::xtest::{self, ConnectionExt as _};
+use x11rb::rust_connection::RustConnection;
+
+use super::{BackendKind, KeyboardBackend};
+use crate::keycodes::{KeyEvent, KeyEvents};
+
+// X keycodes are evdev keycodes shifted by 8
+const EVDEV_OFFSET: u16 = 8;
+
+fn x11_error(err: impl std::fmt::Display) -> io::Error {
+    io::Error::other(format!("X11: {err}"))
+}
+
+pub struct XTestKeyboard {
+    connection: RustConnection,
+    root: Window,
+}
+
+impl XTestKeyboard {
+    pub fn new() -> Result<Self, io::Error> {
+        let (connection, screen) = x11rb::connect(None).map_err(x11_error)?;
+
+        if connection.extension_information(xtest::X11_EXTENSION_NAME).map_err(x11_error)?.is_none() {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "X11: the XTest extension is not available"));
+        }
+
+        let root = connection.setup().roots[screen].root;
+        log_info!(App, "Connected to X11 screen {} for XTest input", screen);
+        Ok(XTestKeyboard { connection, root })
+    }
+
+    fn fake_key(&self, event: &KeyEvent) -> Result<(), io::Error> {
+        let (kind, code) = match event {
+            KeyEvent::Press(key) => (KEY_PRESS_EVENT, key.code),
+            KeyEvent::Release(key) => (KEY_RELEASE_EVENT, key.code),
+        };
+        let detail: u8 = (code + EVDEV_OFFSET).try_into()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("Key code {code} has no X11 keycode")))?;
+
+        self.connection.xtest_fake_input(kind, detail, x11rb::CURRENT_TIME, self.root, 0, 0, 0).map_err(x11_error)?;
+        Ok(())
+    }
+}
+
+impl KeyboardBackend for XTestKeyboard {
+    fn name(&self) -> &'static str {
+        BackendKind::XTest.name()
+    }
+
+    fn write_code(&mut self, event: KeyEvent) -> Result<(), io::Error> {
+        self.fake_key(&event)?;
+        self.connection.flush().map_err(x11_error)
+    }
+
+    // The requests are queued and sent together, in order, by one flush
+    fn write_many(&mut self, events: KeyEvents) {
+        let result = events.iter()
+            .try_for_each(|event| self.fake_key(event))
+            .and_then(|_| self.connection.flush().map_err(x11_error));
+        if let Err(err) = result {
+            log_error!(KeyOut, "[{}]: Failed to write {} events: {}", self.name(), events.len(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use x11rb::protocol::xproto::ConnectionExt as _;
+
+    use super::*;
+    use crate::keycodes::Key;
+
+    // Whether the X server has the key down, asked after the fake input so it's been handled
+    fn is_down(keyboard: &XTestKeyboard, key: Key) -> bool {
+        let keys = keyboard.connection.query_keymap().unwrap().reply().unwrap().keys;
+        let keycode = (key.code + EVDEV_OFFSET) as usize;
+        keys[keycode / 8] & (1 << (keycode % 8)) != 0
+    }
+
+    // Needs an X server, e.g. `xvfb-run cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn types_into_x_server() {
+        let mut keyboard = XTestKeyboard::new().expect("No X server with XTest available");
+        for name in ["shift", "a", "space"] {
+            let key = Key::parse(name).unwrap();
+            keyboard.write_code(KeyEvent::Press(key)).unwrap();
+            assert!(is_down(&keyboard, key), "{name} isn't down");
+            keyboard.write_code(KeyEvent::Release(key)).unwrap();
+            assert!(!is_down(&keyboard, key), "{name} is still down");
+        }
+    }
+}
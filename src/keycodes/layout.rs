@@ -0,0 +1,174 @@
+// What each printable key types on the supported OS layouts, so characters like "@" or "(" are pressed
+// on the key that produces them for the user instead of where a US keyboard has them.
+
+use super::{Key, KeyName};
+
+// Physical keys in the order of the rows below: number row, top, home and bottom row
+const PHYSICAL: [KeyName; 48] = [
+    KeyName::Grave, KeyName::Digit1, KeyName::Digit2, KeyName::Digit3, KeyName::Digit4, KeyName::Digit5,
+    KeyName::Digit6, KeyName::Digit7, KeyName::Digit8, KeyName::Digit9, KeyName::Digit0, KeyName::Minus, KeyName::Equal,
+    KeyName::Q, KeyName::W, KeyName::E, KeyName::R, KeyName::T, KeyName::Y,
+    KeyName::U, KeyName::I, KeyName::O, KeyName::P, KeyName::LeftBrace, KeyName::RightBrace,
+    KeyName::A, KeyName::S, KeyName::D, KeyName::F, KeyName::G, KeyName::H,
+    KeyName::J, KeyName::K, KeyName::L, KeyName::Semicolon, KeyName::Apostrophe, KeyName::Backslash,
+    KeyName::Key102nd, KeyName::Z, KeyName::X, KeyName::C, KeyName::V, KeyName::B,
+    KeyName::N, KeyName::M, KeyName::Comma, KeyName::Dot, KeyName::Slash,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Level {
+    Base,
+    Shift,
+    AltGr,
+}
+
+// One string per level with a character for every PHYSICAL key, a space where the key types nothing
+struct Levels {
+    base: &'static str,
+    shift: &'static str,
+    altgr: &'static str,
+    dead: &'static [(KeyName, Level)], // wait for the next key instead of typing their character
+}
+
+const US: Levels = Levels {
+    base:  "`1234567890-=qwertyuiop[]asdfghjkl;'\\ zxcvbnm,./",
+    shift: "~!@#$%^&*()_+QWERTYUIOP{}ASDFGHJKL:\"| ZXCVBNM<>?",
+    altgr: "                                                ",
+    dead: &[],
+};
+const UK: Levels = Levels {
+    base:  "`1234567890-=qwertyuiop[]asdfghjkl;'#\\zxcvbnm,./",
+    shift: "¬!\"£$%^&*()_+QWERTYUIOP{}ASDFGHJKL:@~|ZXCVBNM<>?",
+    altgr: "¦   €                                           ",
+    dead: &[],
+};
+const DE: Levels = Levels {
+    base:  "^1234567890ß´qwertzuiopü+asdfghjklöä#<yxcvbnm,.-",
+    shift: "°!\"§$%&/()=?`QWERTZUIOPÜ*ASDFGHJKLÖÄ'>YXCVBNM;:_",
+    altgr: "  ²³   {[]}\\ @ €        ~            |      µ   ",
+    dead: &[(KeyName::Grave, Level::Base), (KeyName::Equal, Level::Base), (KeyName::Equal, Level::Shift)],
+};
+const FR: Levels = Levels {
+    base:  "²&é\"'(-è_çà)=azertyuiop^$qsdfghjklmù*<wxcvbn,;:!",
+    shift: " 1234567890°+AZERTYUIOP¨£QSDFGHJKLM%µ>WXCVBN?./§",
+    altgr: "  ~#{[|`\\^@]}  €        ¤                       ",
+    dead: &[
+        (KeyName::LeftBrace, Level::Base), (KeyName::LeftBrace, Level::Shift),
+        (KeyName::Digit2, Level::AltGr), (KeyName::Digit7, Level::AltGr),
+    ],
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    #[default]
+    Us,
+    Uk,
+    De,
+    Fr,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 4] = [Layout::Us, Layout::Uk, Layout::De, Layout::Fr];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Us => "US (QWERTY)",
+            Layout::Uk => "UK (QWERTY)",
+            Layout::De => "German (QWERTZ)",
+            Layout::Fr => "French (AZERTY)",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "us" => Some(Layout::Us),
+            "uk" | "gb" => Some(Layout::Uk),
+            "de" => Some(Layout::De),
+            "fr" => Some(Layout::Fr),
+            _ => None,
+        }
+    }
+
+    fn levels(self) -> &'static Levels {
+        match self {
+            Layout::Us => &US,
+            Layout::Uk => &UK,
+            Layout::De => &DE,
+            Layout::Fr => &FR,
+        }
+    }
+
+    // The key and modifiers that type `c`, preferring keys that aren't dead keys.
+    // Space is the only character outside of the tables
+    pub fn key_for_char(self, c: char) -> Option<Key> {
+        if c == ' ' {
+            return Some(Key { ch: Some(c), ..Key::from(KeyName::Space) });
+        }
+
+        let levels = self.levels();
+        let candidates = [(levels.base, false, false), (levels.shift, true, false), (levels.altgr, false, true)];
+        let mut found = candidates.iter().flat_map(|&(level, shifted, altgr)| {
+            level.chars().zip(PHYSICAL).filter(move |&(typed, _)| typed == c).map(move |(_, id)| {
+                Key { shifted, altgr, ch: Some(c), ..Key::from(id) }
+            })
+        });
+
+        let first = found.next()?;
+        if !self.is_dead_key(first) {
+            return Some(first);
+        }
+        // A dead key only types its character once the next key is pressed, another key for it is better
+        Some(found.find(|key| !self.is_dead_key(*key)).unwrap_or(first))
+    }
+
+    pub fn is_dead_key(self, key: Key) -> bool {
+        let level = match (key.shifted, key.altgr) {
+            (_, true) => Level::AltGr,
+            (true, false) => Level::Shift,
+            (false, false) => Level::Base,
+        };
+        self.levels().dead.contains(&(key.id, level))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output_methods::REGULAR_VP_NOTES;
+
+    #[test]
+    fn levels_cover_every_key() {
+        for layout in Layout::ALL {
+            let levels = layout.levels();
+            for level in [levels.base, levels.shift, levels.altgr] {
+                assert_eq!(level.chars().count(), PHYSICAL.len(), "{} has a level of the wrong length", layout.name());
+            }
+        }
+    }
+
+    #[test]
+    fn us_matches_key_names() {
+        for c in REGULAR_VP_NOTES.iter().map(|c| *c as char) {
+            assert_eq!(Layout::Us.key_for_char(c), Key::parse(&c.to_string()).ok(), "{c:?}");
+        }
+    }
+
+    #[test]
+    fn notes_are_typeable_everywhere() {
+        for layout in Layout::ALL {
+            for c in REGULAR_VP_NOTES.iter().map(|c| *c as char) {
+                assert!(layout.key_for_char(c).is_some(), "{} can't type {c:?}", layout.name());
+            }
+        }
+    }
+
+    #[test]
+    fn other_layouts() {
+        assert_eq!(Layout::De.key_for_char('y'), Some(Key { ch: Some('y'), ..Key::from(KeyName::Z) }));
+        assert_eq!(Layout::De.key_for_char('@'), Some(Key { altgr: true, ch: Some('@'), ..Key::from(KeyName::Q) }));
+        assert_eq!(Layout::Fr.key_for_char('1'), Some(Key { shifted: true, ch: Some('1'), ..Key::from(KeyName::Digit1) }));
+        // The unshifted ^ is a dead key on AZERTY, AltGr+9 types it directly
+        assert_eq!(Layout::Fr.key_for_char('^'), Some(Key { altgr: true, ch: Some('^'), ..Key::from(KeyName::Digit9) }));
+        assert_eq!(Layout::Uk.key_for_char('"'), Some(Key { shifted: true, ch: Some('"'), ..Key::from(KeyName::Digit2) }));
+    }
+}
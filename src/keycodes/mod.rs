@@ -1,8 +1,10 @@
-#![allow(non_camel_case_types)]
 #![allow(dead_code)]
 
 use phf::phf_map;
 
+mod layout;
+pub use layout::Layout;
+
 pub static SHIFTS: phf::Map<&str, &str> = phf_map! {
     "!" => "1", "A" => "a", "K" => "k", "U" => "u",
     "@" => "2", "B" => "b", "L" => "l", "V" => "v",
@@ -14,7 +16,51 @@ pub static SHIFTS: phf::Map<&str, &str> = phf_map! {
     "*" => "8", "H" => "h", "R" => "r",
     "(" => "9", "I" => "i", "S" => "s",
     ")" => "0", "J" => "j", "T" => "t",
+    "_" => "minus", "+" => "equal", "{" => "leftbrace", "}" => "rightbrace",
+    ":" => "semicolon", "\"" => "apostrophe", "|" => "backslash", "~" => "grave",
+    "<" => "comma", ">" => "dot", "?" => "slash",
+};
+// Unshifted characters whose key has a different name
+pub static CHARACTERS: phf::Map<&str, &str> = phf_map! {
+    "-" => "minus", "=" => "equal", "[" => "leftbrace", "]" => "rightbrace",
+    ";" => "semicolon", "'" => "apostrophe", "\\" => "backslash", "`" => "grave",
+    "," => "comma", "." => "dot", "/" => "slash", " " => "space",
 };
+
+// Declares KeyName with one variant per physical key, the single table every lookup goes through
+macro_rules! define_keys {
+    ($($variant:ident = $name:literal => { linux: $linux:expr, windows: $windows:expr, mac: $mac:expr },)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum KeyName {
+            $($variant,)*
+        }
+
+        impl KeyName {
+            pub const ALL: &'static [KeyName] = &[$(KeyName::$variant,)*];
+            pub const COUNT: usize = KeyName::ALL.len();
+
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(KeyName::$variant => $name,)*
+                }
+            }
+
+            pub fn codes(self) -> UniversalKeyCode {
+                match self {
+                    $(KeyName::$variant => UniversalKeyCode { linux: $linux, windows: $windows, mac: $mac },)*
+                }
+            }
+
+            pub fn from_name(name: &str) -> Option<KeyName> {
+                match name {
+                    $($name => Some(KeyName::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
 pub enum KeypressType {
     Release = 0,
     Press = 1,
@@ -23,143 +69,328 @@ pub enum KeypressType {
 
 pub type KeyEvents = Vec<KeyEvent>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum KeyEvent {
     Press(Key),
     Release(Key)
 }
 impl KeyEvent {
+    pub fn key(&self) -> Key {
+        match self {
+            KeyEvent::Press(key) | KeyEvent::Release(key) => *key,
+        }
+    }
+
     pub fn code(&self) -> u16 {
-        match &self {
-            KeyEvent::Press(key) => key.code.clone(),
-            KeyEvent::Release(key) => key.code.clone(),
+        self.key().code
+    }
+}
+impl std::fmt::Display for KeyEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyEvent::Press(key) => write!(f, "press {}", key.name()),
+            KeyEvent::Release(key) => write!(f, "release {}", key.name()),
+        }
+    }
+}
+
+// Keys left held by the events sent so far, to show what's being typed
+#[derive(Debug, Default)]
+pub struct HeldKeys {
+    held: Vec<Key>,
+}
+impl HeldKeys {
+    pub fn track(&mut self, events: &[KeyEvent]) {
+        for event in events {
+            let key = event.key();
+            self.held.retain(|held| held.id != key.id);
+            if let KeyEvent::Press(key) = event {
+                self.held.push(*key);
+            }
         }
     }
+
+    pub fn clear(&mut self) {
+        self.held.clear();
+    }
+
+    // What the held keys type, in the order they were pressed. Modifiers are left out,
+    // keys pressed without a character show their name
+    pub fn labels(&self) -> Vec<String> {
+        self.held.iter()
+            .filter(|key| !key.is_modifier())
+            .map(|key| key.ch.map_or_else(|| key.name().to_owned(), String::from))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownKey(pub String);
+impl std::fmt::Display for UnknownKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unknown key {:?}", self.0)
+    }
 }
+impl std::error::Error for UnknownKey {}
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Key {
-    pub code: u16,
+    pub id: KeyName,
+    pub code: u16, // native code of `id`, cached for the backends
     pub shifted: bool,
+    pub altgr: bool, // needs the right alt key held, only on non-US layouts
+    // Character this key was looked up for. Modifiers pressed only to type it carry it as well,
+    // so backends typing characters directly can skip them
+    pub ch: Option<char>,
 }
 impl Key {
-    pub fn new(s: &str) -> Self {
-        println!("Trying to get {s}");
-        let unshifted = SHIFTS.get(s);
-
-        let code = match unshifted {
-            Some(key) => UniversalKeyCode::get(key),
-            None => UniversalKeyCode::get(s)
-        };
-
-        match code {
-            None => panic!("Invalid key-string supplied"),
-            Some(code) => { Key { code: code.try_into().unwrap(), shifted: unshifted.is_some() } }
+    // Accepts key names ("space", "kp0") and the characters they type ("A", "!", "[").
+    // Meant for loading mappings and user input, the output methods resolve their keys once up front
+    pub fn parse(s: &str) -> Result<Self, UnknownKey> {
+        if let Some(id) = SHIFTS.get(s).and_then(|name| KeyName::from_name(name)) {
+            return Ok(Key { shifted: true, ..Key::from(id) }.typing(s));
         }
+
+        let name = CHARACTERS.get(s).copied().unwrap_or(s);
+        KeyName::from_name(name).map(|id| Key::from(id).typing(s)).ok_or_else(|| UnknownKey(s.to_owned()))
+    }
+
+    // Remembers `s` as the typed character if it is a single one
+    fn typing(self, s: &str) -> Self {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Key { ch: Some(c), ..self },
+            _ => self,
+        }
+    }
+
+    // Presses the key together with the modifiers its character needs, leaving only the key held
+    pub fn press_with_modifiers(self) -> KeyEvents {
+        let modifier = |id: KeyName| Key { ch: self.ch, ..Key::from(id) };
+        let mut events = vec![];
+        if self.shifted { events.push(KeyEvent::Press(modifier(KeyName::Shift))) };
+        if self.altgr { events.push(KeyEvent::Press(modifier(KeyName::RightAlt))) };
+        events.push(KeyEvent::Press(self));
+        if self.altgr { events.push(KeyEvent::Release(modifier(KeyName::RightAlt))) };
+        if self.shifted { events.push(KeyEvent::Release(modifier(KeyName::Shift))) };
+        events
+    }
+
+    pub fn is_modifier(&self) -> bool {
+        self.id.is_modifier()
+    }
+
+    // Name of the physical key, shifted keys report their unshifted key
+    pub fn name(&self) -> &'static str {
+        self.id.name()
+    }
+}
+impl From<KeyName> for Key {
+    fn from(id: KeyName) -> Self {
+        Key { id, code: id.native() as u16, shifted: false, altgr: false, ch: None }
+    }
+}
+
+impl KeyName {
+    pub fn native(self) -> u32 {
+        self.codes().native()
+    }
+
+    pub fn is_modifier(self) -> bool {
+        matches!(
+            self,
+            KeyName::Shift | KeyName::RightShift | KeyName::LeftCtrl | KeyName::RightCtrl
+                | KeyName::LeftAlt | KeyName::RightAlt | KeyName::LeftMeta | KeyName::RightMeta
+        )
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct UniversalKeyCode {
+    linux: u32,
     windows: u32,
     mac: u32,
 }
 impl UniversalKeyCode {
-    #[cfg(not(target_os = "macos"))]
-    pub fn get(s: &str) -> Option<u32> {
-        KEYCODES.get(s).map(|key| key.windows)
+    #[cfg(target_os = "linux")]
+    pub fn native(&self) -> u32 {
+        self.linux
+    }
+    #[cfg(target_os = "windows")]
+    pub fn native(&self) -> u32 {
+        self.windows
     }
     #[cfg(target_os = "macos")]
-    pub fn get(s: &str) -> Option<u32> {
-        KEYCODES.get(s).map(|key| key.mac)
-    }
-}
-
-pub static KEYCODES: phf::Map<&'static str, UniversalKeyCode> = phf_map! {
-    // "ESC" => UniversalKeyCode { windows: 1, mac:  },
-    "1" => UniversalKeyCode { windows: 2, mac: 0x12 },
-    "2" => UniversalKeyCode { windows: 3, mac: 0x13 },
-    "3" => UniversalKeyCode { windows: 4, mac: 0x14 },
-    "4" => UniversalKeyCode { windows: 5, mac: 0x15 },
-    "5" => UniversalKeyCode { windows: 6, mac: 0x17 },
-    "6" => UniversalKeyCode { windows: 7, mac: 0x16 },
-    "7" => UniversalKeyCode { windows: 8, mac: 0x1a },
-    "8" => UniversalKeyCode { windows: 9, mac: 0x1c },
-    "9" => UniversalKeyCode { windows: 10, mac: 0x19 },
-    "0" => UniversalKeyCode { windows: 11, mac: 0x1d },
-    // "MINUS" => UniversalKeyCode { windows: 12, mac:  },
-    // "EQUAL" => UniversalKeyCode { windows: 13, mac:  },
-    "backspace" => UniversalKeyCode { windows: 14, mac: 0x2a },
-    // "TAB" => UniversalKeyCode { windows: 15, mac:  },
-    "q" => UniversalKeyCode { windows: 16, mac: 0x0c },
-    "w" => UniversalKeyCode { windows: 17, mac: 0x0d },
-    "e" => UniversalKeyCode { windows: 18, mac: 0x0e },
-    "r" => UniversalKeyCode { windows: 19, mac: 0x0f },
-    "t" => UniversalKeyCode { windows: 20, mac: 0x11 },
-    "y" => UniversalKeyCode { windows: 21, mac: 0x10 },
-    "u" => UniversalKeyCode { windows: 22, mac: 0x20 },
-    "i" => UniversalKeyCode { windows: 23, mac: 0x22 },
-    "o" => UniversalKeyCode { windows: 24, mac: 0x1f },
-    "p" => UniversalKeyCode { windows: 25, mac: 0x23 },
-    "leftbrace" => UniversalKeyCode { windows: 26, mac: 0x21 },
-    "rightbrace" => UniversalKeyCode { windows: 27, mac: 0x1e },
-    // "enter" => UniversalKeyCode { windows: 28, mac:  },
-    "leftctrl" => UniversalKeyCode { windows: 29, mac: 0x3b },
-    "a" => UniversalKeyCode { windows: 30, mac: 0x00 },
-    "s" => UniversalKeyCode { windows: 31, mac: 0x01 },
-    "d" => UniversalKeyCode { windows: 32, mac: 0x02 },
-    "f" => UniversalKeyCode { windows: 33, mac: 0x03 },
-    "g" => UniversalKeyCode { windows: 34, mac: 0x05 },
-    "h" => UniversalKeyCode { windows: 35, mac: 0x04 },
-    "j" => UniversalKeyCode { windows: 36, mac: 0x26 },
-    "k" => UniversalKeyCode { windows: 37, mac: 0x28 },
-    "l" => UniversalKeyCode { windows: 38, mac: 0x25 },
-    "semicolon" => UniversalKeyCode { windows: 39, mac: 0x29 },
-    // "apostrophe" => UniversalKeyCode { windows: 40, mac:  },
-    "grave" => UniversalKeyCode { windows: 41, mac: 0x32 },
-    "shift" => UniversalKeyCode { windows: 42, mac: 0x38 },
-    // "backslash" => UniversalKeyCode { windows: 43, mac:  },
-    "z" => UniversalKeyCode { windows: 44, mac: 0x06 },
-    "x" => UniversalKeyCode { windows: 45, mac: 0x07 },
-    "c" => UniversalKeyCode { windows: 46, mac: 0x08 },
-    "v" => UniversalKeyCode { windows: 47, mac: 0x09 },
-    "b" => UniversalKeyCode { windows: 48, mac: 0x0b },
-    "n" => UniversalKeyCode { windows: 49, mac: 0x2d },
-    "m" => UniversalKeyCode { windows: 50, mac: 0x2e },
-    // "COMMA" => UniversalKeyCode { windows: 51, mac:  },
-    // "DOT" => UniversalKeyCode { windows: 52, mac:  },
-    // "SLASH" => UniversalKeyCode { windows: 53, mac:  },
-    // "RIGHTSHIFT" => UniversalKeyCode { windows: 54, mac:  },
-    "kpasterisk" => UniversalKeyCode { windows: 55, mac: 0x43 },
-    "leftalt" => UniversalKeyCode { windows: 56, mac: 0x3a },
-    "space" => UniversalKeyCode { windows: 57, mac: 0x31 },
-    // "CAPSLOCK" => UniversalKeyCode { windows: 58, mac:  },
-    // "F1" => UniversalKeyCode { windows: 59, mac:  },
-    // "F2" => UniversalKeyCode { windows: 60, mac:  },
-    // "F3" => UniversalKeyCode { windows: 61, mac:  },
-    // "F4" => UniversalKeyCode { windows: 62, mac:  },
-    // "F5" => UniversalKeyCode { windows: 63, mac:  },
-    // "F6" => UniversalKeyCode { windows: 64, mac:  },
-    // "F7" => UniversalKeyCode { windows: 65, mac:  },
-    // "F8" => UniversalKeyCode { windows: 66, mac:  },
-    // "F9" => UniversalKeyCode { windows: 67, mac:  },
-    // "F10" => UniversalKeyCode { windows: 68, mac:  },
-    // "NUMLOCK" => UniversalKeyCode { windows: 69, mac:  },
-    // "SCROLLLOCK" => UniversalKeyCode { windows: 70, mac:  },
-    "kp7" => UniversalKeyCode { windows: 71, mac: 0x59 },
-    "kp8" => UniversalKeyCode { windows: 72, mac: 0x5b },
-    "kp9" => UniversalKeyCode { windows: 73, mac: 0x5c },
-    "kpminus" => UniversalKeyCode { windows: 74, mac: 0x4e },
-    "kp4" => UniversalKeyCode { windows: 75, mac: 0x56 },
-    "kp5" => UniversalKeyCode { windows: 76, mac: 0x57 },
-    "kp6" => UniversalKeyCode { windows: 77, mac: 0x58 },
-    "kpplus" => UniversalKeyCode { windows: 78, mac: 0x45 },
-    "kp1" => UniversalKeyCode { windows: 79, mac: 0x53 },
-    "kp2" => UniversalKeyCode { windows: 80, mac: 0x54 },
-    "kp3" => UniversalKeyCode { windows: 81, mac: 0x55 },
-    "kp0" => UniversalKeyCode { windows: 82, mac: 0x52 },
-    // "KPDOT" => UniversalKeyCode { windows: 83, mac:  },
-    // "84" => UniversalKeyCode { windows: 84, mac:  },
-    // "F11" => UniversalKeyCode { windows: 87, mac:  },
-    // "F12" => UniversalKeyCode { windows: 88, mac:  },
-};
+    pub fn native(&self) -> u32 {
+        self.mac
+    }
+}
+
+// Linux evdev codes, Windows set 1 scancodes (0xe0__ for extended keys) and macOS virtual keycodes.
+// Names must stay unique per platform, see the tests below
+define_keys! {
+    Esc = "esc" => { linux: 1, windows: 1, mac: 0x35 },
+    Digit1 = "1" => { linux: 2, windows: 2, mac: 0x12 },
+    Digit2 = "2" => { linux: 3, windows: 3, mac: 0x13 },
+    Digit3 = "3" => { linux: 4, windows: 4, mac: 0x14 },
+    Digit4 = "4" => { linux: 5, windows: 5, mac: 0x15 },
+    Digit5 = "5" => { linux: 6, windows: 6, mac: 0x17 },
+    Digit6 = "6" => { linux: 7, windows: 7, mac: 0x16 },
+    Digit7 = "7" => { linux: 8, windows: 8, mac: 0x1a },
+    Digit8 = "8" => { linux: 9, windows: 9, mac: 0x1c },
+    Digit9 = "9" => { linux: 10, windows: 10, mac: 0x19 },
+    Digit0 = "0" => { linux: 11, windows: 11, mac: 0x1d },
+    Minus = "minus" => { linux: 12, windows: 12, mac: 0x1b },
+    Equal = "equal" => { linux: 13, windows: 13, mac: 0x18 },
+    Backspace = "backspace" => { linux: 14, windows: 14, mac: 0x33 },
+    Tab = "tab" => { linux: 15, windows: 15, mac: 0x30 },
+    Q = "q" => { linux: 16, windows: 16, mac: 0x0c },
+    W = "w" => { linux: 17, windows: 17, mac: 0x0d },
+    E = "e" => { linux: 18, windows: 18, mac: 0x0e },
+    R = "r" => { linux: 19, windows: 19, mac: 0x0f },
+    T = "t" => { linux: 20, windows: 20, mac: 0x11 },
+    Y = "y" => { linux: 21, windows: 21, mac: 0x10 },
+    U = "u" => { linux: 22, windows: 22, mac: 0x20 },
+    I = "i" => { linux: 23, windows: 23, mac: 0x22 },
+    O = "o" => { linux: 24, windows: 24, mac: 0x1f },
+    P = "p" => { linux: 25, windows: 25, mac: 0x23 },
+    LeftBrace = "leftbrace" => { linux: 26, windows: 26, mac: 0x21 },
+    RightBrace = "rightbrace" => { linux: 27, windows: 27, mac: 0x1e },
+    Enter = "enter" => { linux: 28, windows: 28, mac: 0x24 },
+    LeftCtrl = "leftctrl" => { linux: 29, windows: 29, mac: 0x3b },
+    A = "a" => { linux: 30, windows: 30, mac: 0x00 },
+    S = "s" => { linux: 31, windows: 31, mac: 0x01 },
+    D = "d" => { linux: 32, windows: 32, mac: 0x02 },
+    F = "f" => { linux: 33, windows: 33, mac: 0x03 },
+    G = "g" => { linux: 34, windows: 34, mac: 0x05 },
+    H = "h" => { linux: 35, windows: 35, mac: 0x04 },
+    J = "j" => { linux: 36, windows: 36, mac: 0x26 },
+    K = "k" => { linux: 37, windows: 37, mac: 0x28 },
+    L = "l" => { linux: 38, windows: 38, mac: 0x25 },
+    Semicolon = "semicolon" => { linux: 39, windows: 39, mac: 0x29 },
+    Apostrophe = "apostrophe" => { linux: 40, windows: 40, mac: 0x27 },
+    Grave = "grave" => { linux: 41, windows: 41, mac: 0x32 },
+    Shift = "shift" => { linux: 42, windows: 42, mac: 0x38 },
+    Backslash = "backslash" => { linux: 43, windows: 43, mac: 0x2a },
+    Z = "z" => { linux: 44, windows: 44, mac: 0x06 },
+    X = "x" => { linux: 45, windows: 45, mac: 0x07 },
+    C = "c" => { linux: 46, windows: 46, mac: 0x08 },
+    V = "v" => { linux: 47, windows: 47, mac: 0x09 },
+    B = "b" => { linux: 48, windows: 48, mac: 0x0b },
+    N = "n" => { linux: 49, windows: 49, mac: 0x2d },
+    M = "m" => { linux: 50, windows: 50, mac: 0x2e },
+    Comma = "comma" => { linux: 51, windows: 51, mac: 0x2b },
+    Dot = "dot" => { linux: 52, windows: 52, mac: 0x2f },
+    Slash = "slash" => { linux: 53, windows: 53, mac: 0x2c },
+    RightShift = "rightshift" => { linux: 54, windows: 54, mac: 0x3c },
+    KpAsterisk = "kpasterisk" => { linux: 55, windows: 55, mac: 0x43 },
+    LeftAlt = "leftalt" => { linux: 56, windows: 56, mac: 0x3a },
+    Space = "space" => { linux: 57, windows: 57, mac: 0x31 },
+    CapsLock = "capslock" => { linux: 58, windows: 58, mac: 0x39 },
+    F1 = "f1" => { linux: 59, windows: 59, mac: 0x7a },
+    F2 = "f2" => { linux: 60, windows: 60, mac: 0x78 },
+    F3 = "f3" => { linux: 61, windows: 61, mac: 0x63 },
+    F4 = "f4" => { linux: 62, windows: 62, mac: 0x76 },
+    F5 = "f5" => { linux: 63, windows: 63, mac: 0x60 },
+    F6 = "f6" => { linux: 64, windows: 64, mac: 0x61 },
+    F7 = "f7" => { linux: 65, windows: 65, mac: 0x62 },
+    F8 = "f8" => { linux: 66, windows: 66, mac: 0x64 },
+    F9 = "f9" => { linux: 67, windows: 67, mac: 0x65 },
+    F10 = "f10" => { linux: 68, windows: 68, mac: 0x6d },
+    NumLock = "numlock" => { linux: 69, windows: 69, mac: 0x47 },
+    ScrollLock = "scrolllock" => { linux: 70, windows: 70, mac: 0x6b },
+    Kp7 = "kp7" => { linux: 71, windows: 71, mac: 0x59 },
+    Kp8 = "kp8" => { linux: 72, windows: 72, mac: 0x5b },
+    Kp9 = "kp9" => { linux: 73, windows: 73, mac: 0x5c },
+    KpMinus = "kpminus" => { linux: 74, windows: 74, mac: 0x4e },
+    Kp4 = "kp4" => { linux: 75, windows: 75, mac: 0x56 },
+    Kp5 = "kp5" => { linux: 76, windows: 76, mac: 0x57 },
+    Kp6 = "kp6" => { linux: 77, windows: 77, mac: 0x58 },
+    KpPlus = "kpplus" => { linux: 78, windows: 78, mac: 0x45 },
+    Kp1 = "kp1" => { linux: 79, windows: 79, mac: 0x53 },
+    Kp2 = "kp2" => { linux: 80, windows: 80, mac: 0x54 },
+    Kp3 = "kp3" => { linux: 81, windows: 81, mac: 0x55 },
+    Kp0 = "kp0" => { linux: 82, windows: 82, mac: 0x52 },
+    KpDot = "kpdot" => { linux: 83, windows: 83, mac: 0x41 },
+    Key102nd = "102nd" => { linux: 86, windows: 86, mac: 0x0a }, // between left shift and Z on ISO keyboards
+    F11 = "f11" => { linux: 87, windows: 87, mac: 0x67 },
+    F12 = "f12" => { linux: 88, windows: 88, mac: 0x6f },
+    KpEnter = "kpenter" => { linux: 96, windows: 0xe01c, mac: 0x4c },
+    RightCtrl = "rightctrl" => { linux: 97, windows: 0xe01d, mac: 0x3e },
+    KpSlash = "kpslash" => { linux: 98, windows: 0xe035, mac: 0x4b },
+    RightAlt = "rightalt" => { linux: 100, windows: 0xe038, mac: 0x3d },
+    Home = "home" => { linux: 102, windows: 0xe047, mac: 0x73 },
+    Up = "up" => { linux: 103, windows: 0xe048, mac: 0x7e },
+    PageUp = "pageup" => { linux: 104, windows: 0xe049, mac: 0x74 },
+    Left = "left" => { linux: 105, windows: 0xe04b, mac: 0x7b },
+    Right = "right" => { linux: 106, windows: 0xe04d, mac: 0x7c },
+    End = "end" => { linux: 107, windows: 0xe04f, mac: 0x77 },
+    Down = "down" => { linux: 108, windows: 0xe050, mac: 0x7d },
+    PageDown = "pagedown" => { linux: 109, windows: 0xe051, mac: 0x79 },
+    Insert = "insert" => { linux: 110, windows: 0xe052, mac: 0x72 },
+    Delete = "delete" => { linux: 111, windows: 0xe053, mac: 0x75 },
+    LeftMeta = "leftmeta" => { linux: 125, windows: 0xe05b, mac: 0x37 },
+    RightMeta = "rightmeta" => { linux: 126, windows: 0xe05c, mac: 0x36 },
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn assert_unique(platform: &str, code: impl Fn(&UniversalKeyCode) -> u32) {
+        let mut seen: HashMap<u32, &str> = HashMap::new();
+        for id in KeyName::ALL {
+            let key = id.codes();
+            if let Some(other) = seen.insert(code(&key), id.name()) {
+                panic!("{} and {other} share {platform} code {:#x}", id.name(), code(&key));
+            }
+        }
+    }
+
+    #[test]
+    fn codes_are_unique_per_platform() {
+        assert_unique("Linux", |key| key.linux);
+        assert_unique("Windows", |key| key.windows);
+        assert_unique("macOS", |key| key.mac);
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for &id in KeyName::ALL {
+            assert_eq!(KeyName::from_name(id.name()), Some(id));
+        }
+    }
+
+    #[test]
+    fn characters_point_to_keys() {
+        for (character, name) in SHIFTS.entries().chain(CHARACTERS.entries()) {
+            assert!(KeyName::from_name(name).is_some(), "{character:?} maps to unknown key {name}");
+            assert!(KeyName::from_name(character).is_none(), "{character:?} is both a character and a key name");
+        }
+    }
+
+    #[test]
+    fn held_keys() {
+        let mut held = HeldKeys::default();
+        held.track(&Key::parse("@").unwrap().press_with_modifiers());
+        held.track(&Key::parse("t").unwrap().press_with_modifiers());
+        held.track(&[KeyEvent::Press(Key::from(KeyName::LeftCtrl)), KeyEvent::Press(Key::from(KeyName::Space))]);
+        assert_eq!(held.labels(), vec!["@", "t", "space"]);
+
+        held.track(&[KeyEvent::Release(Key::parse("2").unwrap())]);
+        assert_eq!(held.labels(), vec!["t", "space"]);
+        held.clear();
+        assert!(held.labels().is_empty());
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Key::parse("a"), Ok(Key { ch: Some('a'), ..Key::from(KeyName::A) }));
+        assert_eq!(Key::parse("A"), Ok(Key { shifted: true, ch: Some('A'), ..Key::from(KeyName::A) }));
+        assert_eq!(Key::parse("["), Ok(Key { ch: Some('['), ..Key::from(KeyName::LeftBrace) }));
+        assert_eq!(Key::parse("space"), Ok(Key::from(KeyName::Space)));
+        assert_eq!(Key::parse("nope"), Err(UnknownKey("nope".to_owned())));
+    }
+}
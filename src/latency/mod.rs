@@ -0,0 +1,204 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const MAX_SAMPLES: usize = 10_000;
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    injected: Instant,
+    latency: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Summary {
+    pub count: usize,
+    pub min: Duration,
+    pub avg: Duration,
+    pub p99: Duration,
+    pub events_per_second: f64,
+}
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.count == 0 {
+            return write!(f, "no events yet");
+        }
+        write!(
+            f, "min {:.2} ms, avg {:.2} ms, p99 {:.2} ms, {:.0} events/s ({} samples)",
+            self.min.as_secs_f64() * 1000.0, self.avg.as_secs_f64() * 1000.0, self.p99.as_secs_f64() * 1000.0,
+            self.events_per_second, self.count
+        )
+    }
+}
+
+pub struct LatencyStats {
+    samples: VecDeque<Sample>,
+    // Pairs a midir timestamp (microseconds, arbitrary origin per connection) with the Instant it corresponds to
+    anchor: Option<(Instant, u64)>,
+    csv: Option<BufWriter<File>>,
+    // Samples not in the CSV yet, written by flush_csv so the MIDI thread doesn't wait on the file
+    unwritten: Vec<Sample>,
+    // Sorting every sample is too slow for each frame, so it's only redone after new ones
+    cached: Option<Summary>,
+    started: Instant,
+}
+
+impl LatencyStats {
+    pub fn new() -> Self {
+        LatencyStats { samples: VecDeque::with_capacity(MAX_SAMPLES), anchor: None, csv: None, unwritten: vec![], cached: None, started: Instant::now() }
+    }
+
+    pub fn set_csv(&mut self, path: &Path) -> Result<(), io::Error> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "time_s,latency_us")?;
+        file.flush()?;
+        self.csv = Some(file);
+        Ok(())
+    }
+
+    /// Forget the timestamp origin, needed whenever the MIDI connection is recreated.
+    pub fn reset_anchor(&mut self) {
+        self.anchor = None;
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.cached = None;
+    }
+
+    /// Converts a midir timestamp into the Instant the message arrived at.
+    ///
+    /// The anchor is moved every time a message would seem to arrive in the future, so it
+    /// settles on the fastest delivery seen and later messages include any extra driver delay.
+    pub fn arrival(&mut self, timestamp: u64) -> Instant {
+        let now = Instant::now();
+
+        if let Some((anchor_instant, anchor_timestamp)) = self.anchor {
+            if timestamp >= anchor_timestamp {
+                let arrival = anchor_instant + Duration::from_micros(timestamp - anchor_timestamp);
+                if arrival <= now {
+                    return arrival;
+                }
+            }
+        }
+
+        self.anchor = Some((now, timestamp));
+        now
+    }
+
+    /// Records one key event injected now for a message that arrived at `arrival`.
+    pub fn record(&mut self, arrival: Instant) {
+        let injected = Instant::now();
+        let sample = Sample { injected, latency: injected.saturating_duration_since(arrival) };
+
+        if self.csv.is_some() {
+            self.unwritten.push(sample);
+        }
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+        self.cached = None;
+    }
+
+    /// Writes the samples recorded since the last call to the live CSV export.
+    pub fn flush_csv(&mut self) {
+        let Some(csv) = self.csv.as_mut() else { return };
+        let started = self.started;
+        let result = self.unwritten.drain(..).try_for_each(|sample| {
+            let time = sample.injected.saturating_duration_since(started).as_secs_f64();
+            writeln!(csv, "{:.6},{}", time, sample.latency.as_micros())
+        });
+        if result.and_then(|_| csv.flush()).is_err() {
+            log_error!(App, "Failed to write latency CSV, disabling export");
+            self.csv = None;
+            self.unwritten.clear();
+        }
+    }
+
+    /// Records `count` key events that were injected together.
+    pub fn record_many(&mut self, arrival: Instant, count: usize) {
+        for _ in 0..count {
+            self.record(arrival);
+        }
+    }
+
+    pub fn summary(&mut self) -> Summary {
+        if self.samples.is_empty() {
+            return Summary::default();
+        }
+
+        let samples = &self.samples;
+        let summary = self.cached.get_or_insert_with(|| {
+            let mut latencies: Vec<Duration> = samples.iter().map(|sample| sample.latency).collect();
+            latencies.sort_unstable();
+
+            let count = latencies.len();
+            let total: Duration = latencies.iter().sum();
+            let p99_index = ((count as f64 * 0.99).ceil() as usize).clamp(1, count) - 1;
+            Summary { count, min: latencies[0], avg: total / count as u32, p99: latencies[p99_index], events_per_second: 0.0 }
+        });
+
+        // The rate drops while nothing happens, so it can't be cached
+        let now = Instant::now();
+        let recent = self.samples.iter().rev()
+            .take_while(|sample| now.saturating_duration_since(sample.injected) <= RATE_WINDOW)
+            .count();
+        Summary { events_per_second: recent as f64 / RATE_WINDOW.as_secs_f64(), ..*summary }
+    }
+
+    /// Writes the retained samples to a CSV file, independent of the live export.
+    pub fn export_csv(&self, path: &Path) -> Result<(), io::Error> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "time_s,latency_us")?;
+        for sample in &self.samples {
+            let time = sample.injected.saturating_duration_since(self.started).as_secs_f64();
+            writeln!(file, "{:.6},{}", time, sample.latency.as_micros())?;
+        }
+        file.flush()
+    }
+}
+
+impl Drop for LatencyStats {
+    fn drop(&mut self) {
+        self.flush_csv();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_follows_new_samples() {
+        let mut stats = LatencyStats::new();
+        stats.record_many(Instant::now(), 2);
+        assert_eq!(stats.summary().count, 2);
+        stats.record(Instant::now() - Duration::from_millis(50));
+        let summary = stats.summary();
+        assert_eq!(summary.count, 3);
+        assert!(summary.p99 >= Duration::from_millis(50));
+        stats.clear();
+        assert_eq!(stats.summary().count, 0);
+    }
+
+    #[test]
+    fn csv_is_written_on_flush() {
+        let path = std::env::temp_dir().join(format!("miditoqwerty-latency-{}.csv", std::process::id()));
+        let mut stats = LatencyStats::new();
+        stats.set_csv(&path).unwrap();
+
+        stats.record_many(Instant::now(), 3);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "time_s,latency_us\n");
+        stats.flush_csv();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 4);
+
+        stats.record(Instant::now());
+        drop(stats);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 5);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
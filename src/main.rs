@@ -1,39 +1,109 @@
 #![windows_subsystem = "console"]
 
-use std::{fmt::Debug, sync::{Arc, Mutex, RwLock}, thread};
+use std::{fmt::Debug, path::Path, sync::{Arc, Mutex, RwLock}, thread, time::Instant};
 
 use eframe::egui;
 use egui::Style;
 use output_methods::InputMethod;
-use keyboard_provider::VirtualKeyboard;
-use keycodes::{Key, KeyEvent, KeyEvents};
-use midir::{Ignore, MidiInput, MidiInputPort};
+use keyboard_provider::{BackendKind, BackendOptions, KeyboardBackend};
+use keycodes::{HeldKeys, KeyEvent, KeyEvents, KeyName, Layout};
+use latency::LatencyStats;
+use focus::{FocusGuard, WindowFilter};
+use commands::{AppCommand, RemoteCommand};
+use midi::{Control, ControlBinding, ControlLayer, NoteState, Player, Recorder, Thru, ThruTarget, Timing};
+use profile::Profile;
+use remote::{RemoteHandler, Status};
+use midir::{Ignore, MidiInput, MidiInputPort, MidiOutput};
+#[cfg(unix)]
+use midir::os::unix::VirtualInput;
 use std::sync::mpsc;
 
 use output_methods::unified::generic_inner;
 use output_methods::unified::pv_inner;
 use output_methods::unified::piano_rooms_inner;
 
-
+#[macro_use]
+mod event_log;
+mod cli;
+mod latency;
 mod output_methods;
 mod keycodes;
+mod focus;
+mod commands;
+mod hotkeys;
+mod midi;
+mod profile;
+mod remote;
+mod sheet;
 
-use midi_event::{self, MidiEventType, Parse};
+use midi_event::{self, Parse};
 
 mod keyboard_provider;
 
-#[allow(dead_code)] // used for comparisons
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum AvailableInputMethod {
     Generic,
     PV,
-    PianoRooms // and this one is never traditionally constructed
+    PianoRooms
+}
+impl AvailableInputMethod {
+    const ALL: [AvailableInputMethod; 3] = [AvailableInputMethod::Generic, AvailableInputMethod::PV, AvailableInputMethod::PianoRooms];
+
+    fn name(self) -> &'static str {
+        match self {
+            AvailableInputMethod::Generic => "Generic",
+            AvailableInputMethod::PV => "Piano Visualizations",
+            AvailableInputMethod::PianoRooms => "Piano Rooms",
+        }
+    }
+    fn description(self) -> &'static str {
+        match self {
+            AvailableInputMethod::Generic => "Basic QWERTY system, no 88-key or velocity support",
+            AvailableInputMethod::PV => "Uses control for 88-key and alt for velocity",
+            AvailableInputMethod::PianoRooms => "Uses the custom numpad input system\nimplemented by Piano Rooms",
+        }
+    }
+    // By name, or shortened to "pv" and the like
+    fn parse(s: &str) -> Option<Self> {
+        let s = s.trim().to_ascii_lowercase().replace(['-', '_'], " ");
+        match s.as_str() {
+            "pv" => Some(AvailableInputMethod::PV),
+            "rooms" => Some(AvailableInputMethod::PianoRooms),
+            _ => Self::ALL.into_iter().find(|method| method.name().eq_ignore_ascii_case(&s)),
+        }
+    }
+    // Wraps around, for cycling through methods with a hotkey
+    fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&method| method == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
 }
 
+#[derive(Clone)]
+enum PortSource {
+    Hardware(MidiInputPort),
+    #[cfg(unix)]
+    Virtual // our own port, which other software connects to
+}
+
+// Name of the virtual port as other software sees it
+#[cfg(unix)]
+const VIRTUAL_PORT_NAME: &str = "miditoqwerty virtual input";
+
 #[derive(Clone)]
 struct MyPortInfo {
-    port: MidiInputPort,
+    source: PortSource,
     name: String
 }
+impl MyPortInfo {
+    fn is_virtual(&self) -> bool {
+        match self.source {
+            PortSource::Hardware(_) => false,
+            #[cfg(unix)]
+            PortSource::Virtual => true,
+        }
+    }
+}
 impl Debug for MyPortInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.name)
@@ -42,23 +112,68 @@ impl Debug for MyPortInfo {
 
 struct Settings {
     output_method: Arc<Mutex<dyn InputMethod + Send>>,
+    method: AvailableInputMethod,
     port: Option<MyPortInfo>,
     output: bool,
-    pv_velocity: bool
+    pv_velocity: bool,
+    layout: Layout,
+    focus: Option<FocusGuard>, // None when the focused window can't be found out
+    transpose: i8, // semitones added to incoming notes
+    control_bindings: Vec<ControlBinding>,
+    programs_select_methods: bool,
+    recorder: Recorder,
+    thru: Thru,
+    notes: Mutex<NoteState>, // what's held after transposing, for the remote control
+    keys: Mutex<HeldKeys>, // what's held on the keyboard output, for the overlay
+    midi_error: Option<String>, // why the selected MIDI input couldn't be opened
+    player: Arc<Player>, // taken out of the settings to play, which waits for the previous playback
+    playback: Timing // quantizing and humanizing of played files, and the tempo of sheets
 }
 impl Settings {
-    fn new(method: AvailableInputMethod) -> Self {
-        match method {
-            AvailableInputMethod::Generic => {
-                Self { output_method: Arc::new(Mutex::new(generic_inner::new())), port: None, output: true, pv_velocity: true }
-            }
-            AvailableInputMethod::PV => {
-                Self { output_method: Arc::new(Mutex::new(pv_inner::new())), port: None, output: true, pv_velocity: true }
-            }
-            AvailableInputMethod::PianoRooms => {
-                Self { output_method: Arc::new(Mutex::new(piano_rooms_inner)), port: None, output: true, pv_velocity: true }
-            }
-        }
+    fn new(method: AvailableInputMethod, layout: Layout) -> Self {
+        let mut settings = Self {
+            output_method: Arc::new(Mutex::new(generic_inner::new(layout))),
+            method: AvailableInputMethod::Generic,
+            port: None,
+            output: true,
+            pv_velocity: true,
+            layout,
+            focus: None,
+            transpose: 0,
+            control_bindings: vec![],
+            programs_select_methods: true,
+            recorder: Recorder::default(),
+            thru: Thru::default(),
+            notes: Mutex::new(NoteState::default()),
+            keys: Mutex::new(HeldKeys::default()),
+            midi_error: None,
+            player: Arc::default(),
+            playback: Timing::default()
+        };
+        settings.set_method(method);
+        settings
+    }
+    fn set_method(&mut self, method: AvailableInputMethod) {
+        self.output_method = match method {
+            AvailableInputMethod::Generic => Arc::new(Mutex::new(generic_inner::new(self.layout))),
+            AvailableInputMethod::PV => Arc::new(Mutex::new(pv_inner::new(self.layout))),
+            AvailableInputMethod::PianoRooms => Arc::new(Mutex::new(piano_rooms_inner)),
+        };
+        self.method = method;
+        self.reset_output_method();
+    }
+    // Forget held keys and pedals, keeping the method's own configuration
+    fn reset_output_method(&self) {
+        let info = match self.pv_velocity {
+            true => "velocity-on",
+            false => "velocity-off"
+        };
+        self.output_method.lock().unwrap().reset(info);
+        self.keys.lock().unwrap().clear();
+    }
+    // Output is on and the target window, if any, has focus
+    fn output_allowed(&self) -> bool {
+        self.output && self.focus.as_ref().is_none_or(|focus| focus.allowed())
     }
     fn set_port(mut self, port: MyPortInfo) -> Self {
         self.port = Some(port);
@@ -66,73 +181,203 @@ impl Settings {
     }
 }
 
-fn midi_update_thread(midi: MidiInput, settings: &Arc<RwLock<Settings>>, keyboard: VirtualKeyboard, settings_update_receiver: mpsc::Receiver<bool>) {
-    let settings = Arc::clone(settings);
+// Every port that can be read from, the virtual port last so hardware is picked first
+fn list_ports(midi: &MidiInput) -> Vec<MyPortInfo> {
+    #[allow(unused_mut)]
+    let mut ports: Vec<MyPortInfo> = midi.ports().into_iter().map(|port| {
+        let name = midi.port_name(&port).unwrap_or("This port is no longer valid.".to_owned());
+        MyPortInfo { source: PortSource::Hardware(port), name }
+    }).collect();
 
-    let keeb = Arc::new(Mutex::new(keyboard));
-    let keeb_clone = Arc::clone(&keeb);
+    #[cfg(unix)]
+    {
+        // Once created, the virtual port is listed like any other, reading from it would read ourselves
+        ports.retain(|port| !port.name.contains(VIRTUAL_PORT_NAME));
+        ports.push(MyPortInfo { source: PortSource::Virtual, name: format!("{VIRTUAL_PORT_NAME} (virtual)") });
+    }
+    ports
+}
 
-    let create_connection = |midi: MidiInput, keeb: Arc<Mutex<VirtualKeyboard>>| {
-        let port = &settings.read().unwrap().port.clone().unwrap().port.clone();
-        let port_name = settings.read().unwrap().port.clone().unwrap().name.clone();
+type SharedKeyboard = Arc<Mutex<Box<dyn KeyboardBackend>>>;
 
-        let settings = Arc::clone(&settings);
-        println!("Connecting to {}", port_name);
-        midi.connect(
-            &port,
-            &port_name,
-            move |_timestamp, message, _| {
-                if !settings.read().unwrap().output {
+fn release_all_keys(keyboard: &mut dyn KeyboardBackend) {
+    let all_key_releases: Vec<KeyEvent> = KeyName::ALL.iter().map(|&id| KeyEvent::Release(id.into())).collect();
+    keyboard.write_many(all_key_releases);
+    log_info!(KeyOut, "Released all keys");
+}
+
+// Replaces the keyboard in use, releasing everything held on the old one first
+fn switch_backend(keyboard: &SharedKeyboard, settings: &Arc<RwLock<Settings>>, kind: BackendKind, options: &BackendOptions) -> bool {
+    match keyboard_provider::create_backend(kind, options) {
+        Ok(backend) => {
+            {
+                let mut keyboard = keyboard.lock().unwrap();
+                release_all_keys(&mut **keyboard);
+                *keyboard = backend;
+            }
+            // Only once the keyboard is unlocked, everything else takes the settings first
+            settings.read().unwrap().reset_output_method();
+            true
+        }
+        Err(err) => {
+            log_error!(App, "Unable to switch to the {} keyboard: {err}", kind.name());
+            false
+        }
+    }
+}
+
+// Everything a message goes through on its way to keys, one per MIDI source
+struct MidiPipeline {
+    settings: Arc<RwLock<Settings>>,
+    keyboard: SharedKeyboard,
+    latency: Arc<Mutex<LatencyStats>>,
+    commands: mpsc::Sender<AppCommand>,
+    control: Option<ControlLayer>
+}
+impl MidiPipeline {
+    fn new(settings: &Arc<RwLock<Settings>>, keyboard: &SharedKeyboard, latency: &Arc<Mutex<LatencyStats>>, commands: &mpsc::Sender<AppCommand>) -> Self {
+        Self {
+            control: Some(ControlLayer::new(settings.read().unwrap().control_bindings.clone())),
+            settings: Arc::clone(settings),
+            keyboard: Arc::clone(keyboard),
+            latency: Arc::clone(latency),
+            commands: commands.clone()
+        }
+    }
+    // For played files, whose program changes shouldn't switch methods
+    fn without_controls(mut self) -> Self {
+        self.control = None;
+        self
+    }
+
+    fn process(&mut self, arrival: Instant, message: &[u8]) {
+        log_trace!(MidiIn, "Raw message: {:?}", message);
+        // Controls keep working while output is off, so it can be turned back on from the instrument
+        if let Some(control) = self.control.as_mut() {
+            let programs_select_methods = self.settings.read().unwrap().programs_select_methods;
+            match control.process(message, programs_select_methods) {
+                Control::Pass => {}
+                Control::Consumed => return,
+                Control::Command(command) => {
+                    // Nothing runs commands anymore while the app shuts down
+                    if self.commands.send(command).is_err() {
+                        log_error!(App, "Unable to run a command from the instrument, the app is shutting down");
+                    }
                     return
                 }
-                let parsed_event = midi_event::Event::parse(message).unwrap();
-                match parsed_event {
-                    midi_event::Event::Midi(event) => {
-                        let keypresses: KeyEvents = match event.event {
-                            MidiEventType::NoteOn(note, velocity) => {
-                                if velocity == 0 { // Some pianos (Alesis Recital Grand, reportedly) send a NoteOn with 0 velocity instead of NoteOff
-                                    settings.try_read().unwrap().output_method.lock().unwrap().release_note(note).clone()
-                                } else { // Non-zero, real down press
-                                    settings.try_read().unwrap().output_method.lock().unwrap().press_note(note, velocity).clone()
-                                }
-                            }
-                            MidiEventType::NoteOff(note, _) => {
-                                settings.try_read().unwrap().output_method.lock().unwrap().release_note(note).clone()
-                            }
-                            MidiEventType::Controller(control, value) => {
-                                match control {
-                                    64 => { settings.try_read().unwrap().output_method.lock().unwrap().process_sustain(value) }
-                                    66 => { settings.try_read().unwrap().output_method.lock().unwrap().process_sostenuto(value) }
-                                    other_control => { println!("Unknown control to set: {other_control}"); vec![] }
-                                }
-                            }
-                            anything_else => {
-                                println!("Unsupported MIDI event type: {:?}", anything_else);
-                                vec![]
-                            }
-                        };
-                        keeb.lock().unwrap().write_many(keypresses);
-                    }
-                    _ => {
-                        println!("Unsupported higher-level event type")
-                    }
+            }
+        }
+
+        let (transpose, allowed) = {
+            let settings = self.settings.read().unwrap();
+            (settings.transpose, settings.output_allowed())
+        };
+        let message = match midi::transpose(message, transpose) {
+            Some(message) => message,
+            None => {
+                log_debug!(MidiIn, "Note transposed out of range, dropping {:?}", message);
+                return
+            }
+        };
+        let message = &*message;
+        {
+            let settings = self.settings.read().unwrap();
+            settings.recorder.record(message);
+            settings.thru.send(message);
+            settings.notes.lock().unwrap().track(message);
+        }
+        if !allowed {
+            return
+        }
+        let parsed_event = match midi_event::Event::parse(message) {
+            Some(event) => event,
+            None => {
+                log_error!(MidiIn, "Failed to parse MIDI message {:?}", message);
+                return
+            }
+        };
+        match parsed_event {
+            midi_event::Event::Midi(event) => {
+                let keypresses: KeyEvents = {
+                    let settings = self.settings.read().unwrap();
+                    let mut output_method = settings.output_method.lock().unwrap();
+                    output_methods::events_for_midi(&mut *output_method, event.event)
+                };
+                if !keypresses.is_empty() {
+                    log_debug!(KeyOut, "{:?}", keypresses);
                 }
-            },
-            ()).unwrap()
+                // All keys of one message go out together, so backends can batch them
+                let count = keypresses.len();
+                self.settings.read().unwrap().keys.lock().unwrap().track(&keypresses);
+                self.keyboard.lock().unwrap().write_many(keypresses);
+                self.latency.lock().unwrap().record_many(arrival, count);
+            }
+            _ => {
+                log_debug!(MidiIn, "Unsupported higher-level event type")
+            }
+        }
+    }
+}
+
+fn midi_update_thread(midi: MidiInput, settings: &Arc<RwLock<Settings>>, keyboard: &SharedKeyboard, latency: &Arc<Mutex<LatencyStats>>, commands: mpsc::Sender<AppCommand>, settings_update_receiver: mpsc::Receiver<bool>) {
+    let settings = Arc::clone(settings);
+    let latency = Arc::clone(latency);
+
+    let keeb = Arc::clone(keyboard);
+    let keeb_clone = Arc::clone(&keeb);
+
+    let create_connection = |midi: MidiInput, keeb: SharedKeyboard| {
+        let MyPortInfo { source, name: port_name } = settings.read().unwrap().port.clone().unwrap();
+
+        let mut pipeline = MidiPipeline::new(&settings, &keeb, &latency, &commands);
+        let latency = Arc::clone(&latency);
+        latency.lock().unwrap().reset_anchor();
+        let callback = move |timestamp, message: &[u8], _: &mut ()| {
+            let arrival = latency.lock().unwrap().arrival(timestamp);
+            pipeline.process(arrival, message);
+        };
+
+        let connection = match source {
+            PortSource::Hardware(port) => {
+                log_info!(App, "Connecting to {}", port_name);
+                midi.connect(&port, &port_name, callback, ())
+            }
+            #[cfg(unix)]
+            PortSource::Virtual => {
+                log_info!(App, "Opening the virtual input port {}", VIRTUAL_PORT_NAME);
+                midi.create_virtual(VIRTUAL_PORT_NAME, callback, ())
+            }
+        };
+        // Stays unconnected until another input is picked, the window shows why
+        let connection = connection.map_err(|err| format!("Unable to open {port_name}: {err}"));
+        if let Err(err) = &connection {
+            log_error!(App, "{err}");
+        }
+        settings.write().unwrap().midi_error = connection.as_ref().err().cloned();
+        connection.ok()
     };
 
     // Check if port exists. if it does and no connection yet, make connection and continue looping maybe?
     let mut connection = create_connection(midi, keeb_clone);
+    let mut on_virtual_port = settings.read().unwrap().port.as_ref().is_some_and(MyPortInfo::is_virtual);
 
     loop {
         // Received signal that settings have changed which require reconnection
         settings_update_receiver.recv().unwrap();
 
-        let all_key_releases: Vec<KeyEvent> = keycodes::KEYCODES.keys().map(|x| KeyEvent::Release(Key::new(*x))).collect();
-        keeb.lock().unwrap().write_many(all_key_releases);
-        println!("Released all keys");
+        release_all_keys(&mut **keeb.lock().unwrap());
+        settings.read().unwrap().keys.lock().unwrap().clear();
 
-        connection.close();
+        // Reopening the virtual port would drop the connections other software made to it
+        let to_virtual_port = settings.read().unwrap().port.as_ref().is_some_and(MyPortInfo::is_virtual);
+        if on_virtual_port && to_virtual_port && connection.is_some() {
+            continue;
+        }
+        on_virtual_port = to_virtual_port;
+
+        if let Some(connection) = connection.take() {
+            connection.close();
+        }
 
         let mut midi_in = MidiInput::new("miditoqwerty input reader").expect("Failed to create MidiInput");
         midi_in.ignore(Ignore::TimeAndActiveSense);
@@ -141,37 +386,339 @@ fn midi_update_thread(midi: MidiInput, settings: &Arc<RwLock<Settings>>, keyboar
     }
 }
 
+// Commands from hotkeys and buttons, which act like the matching controls of the window
+fn run_command(command: AppCommand, settings: &Arc<RwLock<Settings>>, keyboard: &SharedKeyboard, settings_update_tx: &mpsc::Sender<bool>) {
+    log_info!(App, "Running command: {command}");
+    match command {
+        AppCommand::ToggleOutput => {
+            let mut settings = settings.write().unwrap();
+            settings.output = !settings.output;
+            log_info!(App, "Output {}", if settings.output { "enabled" } else { "disabled" });
+        }
+        AppCommand::NextMethod => {
+            let mut settings = settings.write().unwrap();
+            let method = settings.method.next();
+            settings.set_method(method);
+            log_info!(App, "Switched to {}", method.name());
+        }
+        AppCommand::Panic => {
+            // Settings before the keyboard, like the MIDI pipelines
+            let settings = settings.read().unwrap();
+            release_all_keys(&mut **keyboard.lock().unwrap());
+            settings.reset_output_method();
+            settings.thru.all_notes_off();
+            settings.notes.lock().unwrap().releases();
+            return
+        }
+        AppCommand::Transpose(semitones) => {
+            let mut settings = settings.write().unwrap();
+            settings.transpose = settings.transpose.saturating_add(semitones).clamp(-midi::MAX_TRANSPOSE, midi::MAX_TRANSPOSE);
+            // Held notes are released on other keys now, the method would keep counting the old ones
+            settings.reset_output_method();
+            settings.thru.all_notes_off();
+            log_info!(App, "Transpose: {:+}", settings.transpose);
+        }
+        AppCommand::ResetTranspose => {
+            let mut settings = settings.write().unwrap();
+            settings.transpose = 0;
+            settings.reset_output_method();
+            settings.thru.all_notes_off();
+        }
+        AppCommand::SelectMethod(index) => {
+            let Some(&method) = AvailableInputMethod::ALL.get(index as usize) else {
+                log_warn!(App, "There's no output method {}", index as u16 + 1);
+                return
+            };
+            settings.write().unwrap().set_method(method);
+            log_info!(App, "Switched to {}", method.name());
+        }
+        AppCommand::ToggleRecording => {
+            let settings = settings.read().unwrap();
+            match settings.recorder.stop(std::path::Path::new(".")) {
+                Some(Ok(path)) => log_info!(App, "Saved recording to {}", path.display()),
+                Some(Err(err)) => log_error!(App, "Failed to save recording: {err}"),
+                None => {
+                    settings.recorder.start();
+                    log_info!(App, "Recording started");
+                }
+            }
+            return
+        }
+        AppCommand::StopPlayback => {
+            settings.read().unwrap().player.stop();
+            return
+        }
+    }
+    // Held notes would be released on the wrong keys otherwise
+    settings_update_tx.send(true).expect("Failed to update listener");
+}
+
+// Plays a MIDI file or a sheet (.txt) as if it came from the instrument, stopping whatever was playing
+fn play_file(path: &Path, settings: &Arc<RwLock<Settings>>, keyboard: &SharedKeyboard, latency: &Arc<Mutex<LatencyStats>>, commands: &mpsc::Sender<AppCommand>) -> Result<(), String> {
+    let messages = match path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("txt")) {
+        true => {
+            let text = std::fs::read_to_string(path).map_err(|err| format!("Unable to read {}: {err}", path.display()))?;
+            sheet::parse(&text).map_err(|err| format!("{}: {err}", path.display()))?
+        }
+        false => midi::read_file(path).map_err(|err| format!("Unable to read {}: {err}", path.display()))?,
+    };
+    let timing = settings.read().unwrap().playback;
+    let messages = match timing.changes_anything() {
+        true => {
+            // Jitter differs every time the file is played
+            let seed = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |since| since.as_nanos() as u64);
+            midi::apply_timing(&messages, &timing, seed)
+        }
+        false => messages,
+    };
+    let name = path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().into_owned();
+    let mut pipeline = MidiPipeline::new(settings, keyboard, latency, commands).without_controls();
+    // Not under the settings, the previous playback needs them to release its notes
+    let player = settings.read().unwrap().player.clone();
+    player.play(name, messages, move |message| pipeline.process(Instant::now(), message));
+    Ok(())
+}
+
+// Writes a MIDI file as a sheet next to it, as <file>.txt
+fn export_sheet(path: &Path, mapping: sheet::Mapping, bpm: f64) -> Result<std::path::PathBuf, String> {
+    let messages = midi::read_file(path).map_err(|err| format!("Unable to read {}: {err}", path.display()))?;
+    let transcription = sheet::transcribe(&messages, mapping, bpm);
+    if transcription.skipped > 0 {
+        log_warn!(App, "Left out {} notes {} has no key for", transcription.skipped, mapping.name());
+    }
+    let sheet_path = path.with_extension("txt");
+    std::fs::write(&sheet_path, transcription.text).map_err(|err| format!("Unable to write {}: {err}", sheet_path.display()))?;
+    log_info!(App, "Wrote the sheet to {}", sheet_path.display());
+    Ok(sheet_path)
+}
+
+// Applies the settings a profile has, like choosing them in the window
+fn load_profile(path: &Path, settings: &Arc<RwLock<Settings>>, keyboard: &SharedKeyboard, settings_update_tx: &mpsc::Sender<bool>) -> Result<(), String> {
+    let profile = Profile::load(path)?;
+    {
+        let mut settings = settings.write().unwrap();
+        if let Some(layout) = profile.layout {
+            settings.layout = layout;
+            settings.output_method.lock().unwrap().set_layout(layout);
+        }
+        if let Some(velocity) = profile.velocity {
+            settings.pv_velocity = velocity;
+        }
+        let method = profile.method.unwrap_or(settings.method);
+        settings.set_method(method);
+        if let Some(transpose) = profile.transpose {
+            settings.transpose = transpose;
+            settings.thru.all_notes_off();
+        }
+        if let Some(output) = profile.output {
+            settings.output = output;
+        }
+        if let Some(programs_select_methods) = profile.programs_select_methods {
+            settings.programs_select_methods = programs_select_methods;
+        }
+        if let Some(filter) = profile.target_window {
+            match &settings.focus {
+                Some(focus) => focus.set_filter(filter),
+                None => log_warn!(App, "Targeting a window is unavailable, ignoring the profile's target window"),
+            }
+        }
+    }
+    // Once the settings are unlocked, nothing holding the keyboard waits on them then
+    if let Some(layout) = profile.layout {
+        keyboard.lock().unwrap().set_layout(layout);
+    }
+    log_info!(App, "Loaded profile {}", path.display());
+    settings_update_tx.send(true).map_err(|_| "The app is shutting down".to_owned())
+}
+
+// What the remote control sees and does
+struct RemoteControl {
+    settings: Arc<RwLock<Settings>>,
+    keyboard: SharedKeyboard,
+    latency: Arc<Mutex<LatencyStats>>,
+    commands: Mutex<mpsc::Sender<AppCommand>>,
+    settings_update_tx: Mutex<mpsc::Sender<bool>>
+}
+impl RemoteHandler for RemoteControl {
+    fn status(&self) -> Status {
+        let settings = self.settings.read().unwrap();
+        let notes = settings.notes.lock().unwrap();
+        let held_keys = settings.keys.lock().unwrap().labels();
+        Status {
+            port: settings.port.as_ref().map(|port| port.name.clone()).unwrap_or_default(),
+            method: settings.method.name().to_owned(),
+            layout: settings.layout.name().to_owned(),
+            output: settings.output,
+            output_allowed: settings.output_allowed(),
+            transpose: settings.transpose,
+            held_notes: notes.notes(),
+            held_keys,
+            sustain: notes.sustain,
+            sostenuto: notes.sostenuto,
+            latency: self.latency.lock().unwrap().summary(),
+            recording: settings.recorder.is_recording(),
+            playing: settings.player.playing(),
+        }
+    }
+
+    fn command(&self, command: &str) -> Result<(), String> {
+        let commands = self.commands.lock().unwrap().clone();
+        match RemoteCommand::parse(command)? {
+            RemoteCommand::App(command) => commands.send(command).map_err(|_| "The app is shutting down".to_owned()),
+            RemoteCommand::LoadProfile(path) => {
+                let settings_update_tx = self.settings_update_tx.lock().unwrap().clone();
+                load_profile(&path, &self.settings, &self.keyboard, &settings_update_tx)
+            }
+            RemoteCommand::Play(path) => play_file(&path, &self.settings, &self.keyboard, &self.latency, &commands),
+        }
+    }
+}
+
+// Shows a small window explaining why the app can't continue, returns once it's closed
+fn show_error(message: String) {
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_inner_size([420.0, 160.0]),
+        ..Default::default()
+    };
+    let _ = eframe::run_simple_native("Midi to Qwerty [error]", options, move |ctx, _frame| {
+        egui::CentralPanel::default().show(ctx, |ui| { ui.label(&message); });
+    });
+}
+
 fn main() -> eframe::Result<()> {
-    let virtual_keyboard = keyboard_provider::create_virtual_keyboard();
+    let cli_options = match cli::CliOptions::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{err}\n\n{}", cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if cli_options.help {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+    if cli_options.print_udev_rule {
+        match keyboard_provider::udev_setup() {
+            Some(setup) => print!("{setup}"),
+            None => eprintln!("udev rules are only needed on Linux"),
+        }
+        return Ok(());
+    }
+
+    if let Some(level) = cli_options.log_level {
+        event_log::set_stdout_level(level);
+    }
+    if let Some(capacity) = cli_options.log_capacity {
+        event_log::set_capacity(capacity);
+    }
+    if let Some(path) = &cli_options.log_file {
+        if let Err(err) = event_log::set_file(path) {
+            eprintln!("Unable to open log file {}: {err}", path.display());
+        }
+    }
+
+    if let Some(path) = &cli_options.export_sheet {
+        let mapping = cli_options.sheet_mapping.unwrap_or(sheet::Mapping::Generic);
+        if let Err(err) = export_sheet(path, mapping, cli_options.timing.bpm) {
+            log_error!(App, "{err}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let latency = Arc::new(Mutex::new(LatencyStats::new()));
+    if let Some(path) = &cli_options.latency_csv {
+        if let Err(err) = latency.lock().unwrap().set_csv(path) {
+            log_error!(App, "Unable to create latency CSV {}: {err}", path.display());
+        }
+    }
+    if cli_options.latency_report || cli_options.latency_csv.is_some() {
+        let latency = Arc::clone(&latency);
+        let report = cli_options.latency_report;
+        thread::spawn(move || loop {
+            std::thread::sleep(std::time::Duration::from_secs(5));
+            let mut latency = latency.lock().unwrap();
+            latency.flush_csv();
+            if report {
+                log_info!(App, "Latency: {}", latency.summary());
+            }
+        });
+    }
+
+    let mut backend_options = BackendOptions {
+        dry_run_file: cli_options.dry_run_file.clone(),
+        network_address: cli_options.forward_to.clone(),
+        type_characters: cli_options.type_characters,
+        layout: cli_options.layout.unwrap_or_default(),
+        ..Default::default()
+    };
+    if let Some(name) = &cli_options.device_name {
+        backend_options.device_name = name.clone();
+    }
+
+    if let Some(address) = &cli_options.key_server {
+        let backend = match keyboard_provider::create_default_backend(&backend_options) {
+            Ok((_, backend)) => backend,
+            Err(err) => {
+                log_error!(App, "{err}");
+                std::process::exit(1);
+            }
+        };
+        if let Err(err) = keyboard_provider::serve_keys(address, backend) {
+            log_error!(App, "Key server on {address} failed: {err}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let created_backend = match cli_options.backend {
+        Some(kind) => keyboard_provider::create_backend(kind, &backend_options)
+            .map(|backend| (kind, backend))
+            .map_err(|err| format!("Unable to use the {} keyboard: {err}", kind.name())),
+        None => keyboard_provider::create_default_backend(&backend_options),
+    };
+    let (mut backend_kind, backend) = match created_backend {
+        Ok(created) => created,
+        Err(err) => {
+            log_error!(App, "{err}");
+            show_error(err);
+            std::process::exit(1);
+        }
+    };
+    let keyboard: SharedKeyboard = Arc::new(Mutex::new(backend));
+
+    // Another backend took over, but the user should still know why their usual one didn't work
+    let mut native_problem = match cli_options.backend {
+        None if backend_kind != BackendKind::Native => keyboard_provider::native_problem(),
+        _ => None,
+    };
+    let mut network_address = cli_options.forward_to.clone().unwrap_or_default();
+    let mut device_name = backend_options.device_name.clone();
+    let mut type_characters = backend_options.type_characters;
 
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default().with_inner_size([320.0, 240.0]),
         ..Default::default()
     };
 
-    // let mut logs: Vec<String> = Vec::new();
-
     let mut midi_in = MidiInput::new("miditoqwerty input reader").expect("Failed to create MidiInput");
     midi_in.ignore(Ignore::TimeAndActiveSense);
-    let midi_in = Some(midi_in);
 
     // Immutable and does not handle actual input reading (.connect is never called), etc.
     let meta_midi_in = MidiInput::new("miditoqwerty meta reader").expect("Unable to create meta MidiInput");
 
-    let ports = Arc::new(RwLock::new(meta_midi_in.ports().into_iter().map(|port| {
-            MyPortInfo { port: port.clone(), name: meta_midi_in.port_name(&port).unwrap_or("This port is no longer valid.".to_owned()) }
-        }).collect::<Vec<MyPortInfo>>())); // initial port population
+    let ports = Arc::new(RwLock::new(list_ports(&meta_midi_in))); // initial port population
 
-    dbg!(ports.clone());
+    log_debug!(App, "Initial ports: {:?}", ports.read().unwrap());
 
-    let first_port = ports.read().unwrap().first().cloned();
+    let first_port = {
+        let ports = ports.read().unwrap();
+        ports.iter().find(|port| port.is_virtual() == cli_options.virtual_port).or(ports.first()).cloned()
+    };
     let first_port = Arc::new(RwLock::new(match first_port {
         Some(port) => port,
         None => {
-            let mut options_for_popup = options.clone();
-            options_for_popup.viewport = egui::ViewportBuilder::default().with_inner_size([320.0, 120.0]);
-
-            let _ = eframe::run_simple_native("Midi to Qwerty [error]", options.clone(), move |ctx, _frame| { egui::CentralPanel::default().show(ctx, |ui| { ui.label("No available input ports found"); }); });
+            show_error("No available input ports found".to_owned());
             panic!("At least one input port is needed");
         }
     }));
@@ -186,15 +733,11 @@ fn main() -> eframe::Result<()> {
         loop {
             {
                 let mut ports = port_list_ports_clone.write().unwrap();
-                *ports = meta_midi_in
-                    .ports()
-                    .into_iter()
-                    .map(|port| {
-                        MyPortInfo { port: port.clone(), name: meta_midi_in.port_name(&port).unwrap_or("This port is no longer valid.".to_owned()) }
-                    }).collect::<Vec<MyPortInfo>>();
+                *ports = list_ports(&meta_midi_in);
+            }
+            if let Some(port) = port_list_ports_clone.read().unwrap().first().cloned() {
+                *port_list_first_port_clone.write().unwrap() = port;
             }
-            let mut first_port = port_list_first_port_clone.write().unwrap();
-            *first_port = port_list_ports_clone.read().unwrap().first().cloned().expect("At least one input port is needed");
 
             std::thread::sleep(std::time::Duration::from_millis(500));
         }
@@ -204,38 +747,132 @@ fn main() -> eframe::Result<()> {
     let port_wait_ports_clone = Arc::clone(&ports);
     loop { // Wait for thread above to update the value
         std::thread::sleep(std::time::Duration::from_millis(50));
-        if port_wait_ports_clone.read().unwrap().len() > 0 {
+        if !port_wait_ports_clone.read().unwrap().is_empty() {
             break
         };
     }
 
-    println!("Available ports: {:?}", ports.read().unwrap());
+    log_info!(App, "Available ports: {:?}", ports.read().unwrap());
 
     let settings = Arc::new(RwLock::new(
-        Settings::new(AvailableInputMethod::Generic)
-            .set_port({
-                let port = first_port.read().unwrap().port.clone();
-                let name = meta_midi_in.port_name(&port).unwrap();
-                MyPortInfo {
-                    port,
-                    name
-                }
-            })
+        Settings::new(AvailableInputMethod::Generic, cli_options.layout.unwrap_or_default())
+            .set_port(first_port.read().unwrap().clone())
     ));
 
+    let focus_guard = match focus::create_focus_query() {
+        Ok(query) => {
+            let keyboard = Arc::clone(&keyboard);
+            let settings = Arc::clone(&settings);
+            // Settings before the keyboard, the order the MIDI pipelines take them in
+            Some(FocusGuard::start(query, cli_options.target_window.clone(), move || {
+                settings.read().unwrap().reset_output_method();
+                release_all_keys(&mut **keyboard.lock().unwrap());
+            }))
+        }
+        Err(err) => {
+            match cli_options.target_window {
+                Some(_) => log_error!(App, "Unable to target a window: {err}"),
+                None => log_info!(App, "Targeting a window is unavailable: {err}"),
+            }
+            None
+        }
+    };
+    {
+        let mut settings = settings.write().unwrap();
+        settings.focus = focus_guard.clone();
+        settings.control_bindings = cli_options.controls.clone();
+        settings.programs_select_methods = !cli_options.no_program_change;
+        settings.playback = cli_options.timing;
+    }
+
+    // Immutable, only lists outputs for MIDI thru
+    let meta_midi_out = MidiOutput::new("miditoqwerty thru lister").expect("Unable to create meta MidiOutput");
+    if let Some(pattern) = &cli_options.thru {
+        match ThruTarget::find(&meta_midi_out, pattern) {
+            Some(target) => match settings.read().unwrap().thru.connect(&target) {
+                Ok(()) => log_info!(App, "Forwarding MIDI to {}", target.name()),
+                Err(err) => log_error!(App, "Unable to forward MIDI to {}: {err}", target.name()),
+            },
+            None => log_error!(App, "No MIDI output matches {pattern:?}"),
+        }
+    }
+    let mut target_window = cli_options.target_window.as_ref().map(|filter| filter.to_string()).unwrap_or_default();
+    let mut midi_file_path = String::new();
+    let mut profile_path = cli_options.profile.as_ref().map(|path| path.display().to_string()).unwrap_or_default();
+
     // If anything is transmitted to this receiver, midi_update_thread restarts the MIDI connection with the new &settings
     let (settings_update_tx, settings_update_rx): (mpsc::Sender<bool>, mpsc::Receiver<bool>) = mpsc::channel();
 
+    let (command_tx, command_rx) = mpsc::channel::<AppCommand>();
+
     thread::spawn({
         let settings = Arc::clone(&settings);
+        let keyboard = Arc::clone(&keyboard);
+        let latency = Arc::clone(&latency);
+        let command_tx = command_tx.clone();
         move || {
-            midi_update_thread(midi_in.unwrap(), &settings, virtual_keyboard, settings_update_rx)
+            midi_update_thread(midi_in, &settings, &keyboard, &latency, command_tx, settings_update_rx)
+        }
+    });
+    thread::spawn({
+        let settings = Arc::clone(&settings);
+        let keyboard = Arc::clone(&keyboard);
+        let settings_update_tx = settings_update_tx.clone();
+        move || {
+            for command in command_rx {
+                run_command(command, &settings, &keyboard, &settings_update_tx);
+            }
         }
     });
 
+    if let Some(path) = &cli_options.profile {
+        if let Err(err) = load_profile(path, &settings, &keyboard, &settings_update_tx) {
+            log_error!(App, "{err}");
+        }
+    }
+    if let Some(address) = &cli_options.remote {
+        let handler = Arc::new(RemoteControl {
+            settings: Arc::clone(&settings),
+            keyboard: Arc::clone(&keyboard),
+            latency: Arc::clone(&latency),
+            commands: Mutex::new(command_tx.clone()),
+            settings_update_tx: Mutex::new(settings_update_tx.clone())
+        });
+        match remote::serve(address, handler) {
+            Ok(local) => log_info!(App, "Remote control listening on http://{local}"),
+            Err(err) => log_error!(App, "Unable to start the remote control on {address}: {err}"),
+        }
+    }
+
+    // Network sources play alongside the selected port, each through its own pipeline
+    if let Some(address) = &cli_options.udp_midi {
+        let mut pipeline = MidiPipeline::new(&settings, &keyboard, &latency, &command_tx);
+        match midi::listen_udp(address, move |message| pipeline.process(Instant::now(), message)) {
+            Ok(local) => log_info!(App, "Receiving MIDI over UDP on {local}"),
+            Err(err) => log_error!(App, "Unable to receive MIDI over UDP on {address}: {err}"),
+        }
+    }
+    if let Some(address) = &cli_options.rtp_midi {
+        let mut pipeline = MidiPipeline::new(&settings, &keyboard, &latency, &command_tx);
+        match midi::listen_rtp_midi(address, move |message| pipeline.process(Instant::now(), message)) {
+            Ok(local) => log_info!(App, "Accepting RTP-MIDI sessions on {local}"),
+            Err(err) => log_error!(App, "Unable to accept RTP-MIDI sessions on {address}: {err}"),
+        }
+    }
+
+    if !cli_options.no_hotkeys {
+        let bindings = cli_options.hotkeys.clone().unwrap_or_else(hotkeys::default_bindings);
+        match hotkeys::start(bindings.clone(), command_tx.clone(), &backend_options.device_name) {
+            Ok(()) => log_info!(App, "Hotkeys: {}", bindings.iter().map(|binding| binding.to_string()).collect::<Vec<_>>().join(", ")),
+            Err(err) => log_warn!(App, "Global hotkeys are unavailable: {err}"),
+        }
+    }
+
     let settings = Arc::clone(&settings);
     let mut did_style = false;
-    eframe::run_simple_native("Midi to Qwerty", options, move |ctx, _frame| {
+    let mut log_filter = event_log::Filter::default();
+    let exit_latency = Arc::clone(&latency);
+    let result = eframe::run_simple_native("Midi to Qwerty", options, move |ctx, _frame| {
         if !did_style {
             did_style = true;
             ctx.set_style({
@@ -249,61 +886,73 @@ fn main() -> eframe::Result<()> {
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading("Midi to Qwerty");
 
+            if let Some(problem) = &native_problem {
+                let mut dismissed = false;
+                egui::Window::new(format!("{} unavailable", BackendKind::Native.name()))
+                    .collapsible(false)
+                    .show(ctx, |ui| {
+                        ui.label(problem.as_str());
+                        ui.label(format!("Using the {} keyboard instead.", backend_kind.name()));
+                        dismissed = ui.button("OK").clicked();
+                    });
+                if dismissed {
+                    native_problem = None;
+                }
+            }
+
             let selected_midi_port_name = settings.read().unwrap().port.clone().unwrap().name;
             egui::ComboBox::from_label("MIDI Input")
                 .selected_text(selected_midi_port_name)
                 .show_ui(ui, |ui| {
-                    for (port, port_name) in meta_midi_in.ports().iter().map(|port| {
-                        (port, meta_midi_in.port_name(port).unwrap_or("This port is no longer valid.".to_owned()))
-                    }).collect::<Vec<(&MidiInputPort, String)>>() {
-                        if ui.selectable_label(false, port_name.clone()).clicked() {
-                            settings.write().unwrap().port = Some(MyPortInfo { port: port.clone(), name: port_name.clone() });
+                    for port in list_ports(&meta_midi_in) {
+                        if ui.selectable_label(false, port.name.clone()).clicked() {
+                            settings.write().unwrap().port = Some(port);
                             settings_update_tx.send(true).expect("Failed to update listener");
                         }
                     }
                 });
+            if let Some(err) = settings.read().unwrap().midi_error.clone() {
+                ui.colored_label(egui::Color32::LIGHT_RED, err);
+            }
 
-            let selected_output_method = {
-                let settings_read = settings.try_read().unwrap();
-                let output_method = settings_read.output_method.lock().unwrap();
-                output_method.get_name()
-            };
-
+            let selected_output_method = settings.read().unwrap().method;
             egui::ComboBox::from_label("Output Method")
-                .selected_text(&selected_output_method)
+                .selected_text(selected_output_method.name())
                 .show_ui(ui, |ui| {
-                    if ui.selectable_label(false, "Generic").on_hover_text("Basic QWERTY system, no 88-key or velocity support").clicked() {
-                        let mut my_settings = settings.write().unwrap();
-                        settings_update_tx.send(true).expect("Failed to update listener");
-                        my_settings.output_method = Arc::new(Mutex::new(generic_inner::new()))
-                    }
-                    if ui.selectable_label(false, "Piano Visualizations").on_hover_text("Uses control for 88-key and alt for velocity").clicked() {
-                        let mut my_settings = settings.write().unwrap();
-                        settings_update_tx.send(true).expect("Failed to update listener");
-                        let velocity_info = match my_settings.pv_velocity {
-                            true => "velocity-on",
-                            false => "velocity-off"
-                        };
-                        my_settings.output_method = Arc::new(Mutex::new(pv_inner::new()));
-                        my_settings.output_method.lock().unwrap().reset(velocity_info);
-                    }
-                    if ui.selectable_label(false, "Piano Rooms").on_hover_text("Uses the custom numpad input system\nimplemented by Piano Rooms").clicked() {
-                        let mut my_settings = settings.write().unwrap();
-                        settings_update_tx.send(true).expect("Failed to update listener");
-                        my_settings.output_method = Arc::new(Mutex::new(piano_rooms_inner))
+                    for method in AvailableInputMethod::ALL {
+                        if ui.selectable_label(method == selected_output_method, method.name()).on_hover_text(method.description()).clicked() {
+                            settings.write().unwrap().set_method(method);
+                            settings_update_tx.send(true).expect("Failed to update listener");
+                        }
                     }
                 });
 
-            if &selected_output_method == "Piano Visualizations" {
-                if ui.checkbox(&mut settings.write().unwrap().pv_velocity, "Use velocity (alt)").clicked() {
-                    settings_update_tx.send(true).expect("Failed to update listener");
-                    let my_settings = settings.write().unwrap();
-                    let info = match my_settings.pv_velocity {
-                        true => "velocity-on",
-                        false => "velocity-off"
-                    };
-                    my_settings.output_method.lock().unwrap().reset(info);
-                }
+            let selected_layout = settings.read().unwrap().layout;
+            egui::ComboBox::from_label("Keyboard Layout")
+                .selected_text(selected_layout.name())
+                .show_ui(ui, |ui| {
+                    for layout in Layout::ALL {
+                        if ui.selectable_label(layout == selected_layout, layout.name()).clicked() && layout != selected_layout {
+                            {
+                                let mut my_settings = settings.write().unwrap();
+                                my_settings.layout = layout;
+                                my_settings.output_method.lock().unwrap().set_layout(layout);
+                            }
+                            keyboard.lock().unwrap().set_layout(layout);
+                            settings_update_tx.send(true).expect("Failed to update listener");
+                        }
+                    }
+                }).response.on_hover_text("The layout set in the OS, so characters are typed on the keys that produce them");
+
+            if selected_output_method == AvailableInputMethod::PV
+                && ui.checkbox(&mut settings.write().unwrap().pv_velocity, "Use velocity (alt)").clicked() {
+                settings_update_tx.send(true).expect("Failed to update listener");
+                let my_settings = settings.write().unwrap();
+                let info = match my_settings.pv_velocity {
+                    true => "velocity-on",
+                    false => "velocity-off"
+                };
+                my_settings.output_method.lock().unwrap().reset(info);
             }
 
             if ui.checkbox(&mut settings.write().unwrap().output, "Enable output").clicked() {
@@ -312,11 +961,294 @@ fn main() -> eframe::Result<()> {
                 my_settings.output_method.lock().unwrap().reset("");
             }
 
-            // egui::ScrollArea::vertical().show(ui, |ui| {
-            //     for log in logs.iter() {
-            //         ui.label(log);
-            //     }
-            // })
+            ui.horizontal(|ui| {
+                let mut transpose = settings.read().unwrap().transpose;
+                let drag = ui.add(egui::DragValue::new(&mut transpose).clamp_range(-midi::MAX_TRANSPOSE..=midi::MAX_TRANSPOSE).prefix("Transpose: "))
+                    .on_hover_text("Semitones added to every incoming note");
+                if drag.changed() {
+                    let current = settings.read().unwrap().transpose;
+                    command_tx.send(AppCommand::Transpose(transpose - current)).expect("Failed to send command");
+                }
+                if ui.button("Panic").on_hover_text("Release every key and forget held notes and pedals").clicked() {
+                    command_tx.send(AppCommand::Panic).expect("Failed to send command");
+                }
+                let record_label = if settings.read().unwrap().recorder.is_recording() { "Stop recording" } else { "Record" };
+                if ui.button(record_label).on_hover_text("Record incoming MIDI to a .mid file in the working directory").clicked() {
+                    command_tx.send(AppCommand::ToggleRecording).expect("Failed to send command");
+                }
+            });
+
+            let thru_target = settings.read().unwrap().thru.target();
+            egui::ComboBox::from_label("MIDI Thru")
+                .selected_text(thru_target.as_deref().unwrap_or("Off"))
+                .show_ui(ui, |ui| {
+                    if ui.selectable_label(thru_target.is_none(), "Off").clicked() {
+                        settings.read().unwrap().thru.disconnect();
+                    }
+                    for target in ThruTarget::list(&meta_midi_out) {
+                        let name = target.name();
+                        if ui.selectable_label(thru_target.as_ref() == Some(&name), name.as_str()).clicked() {
+                            match settings.read().unwrap().thru.connect(&target) {
+                                Ok(()) => log_info!(App, "Forwarding MIDI to {name}"),
+                                Err(err) => log_error!(App, "Unable to forward MIDI to {name}: {err}"),
+                            }
+                        }
+                    }
+                }).response.on_hover_text("Also send incoming MIDI, after transposing, to a synth or other software");
+
+            if ui.checkbox(&mut settings.write().unwrap().programs_select_methods, "Program Change selects method")
+                .on_hover_text("Program 0 selects Generic, 1 Piano Visualizations and 2 Piano Rooms").clicked() {
+                settings_update_tx.send(true).expect("Failed to update listener");
+            }
+
+            let current_options = BackendOptions {
+                device_name: device_name.clone(),
+                network_address: Some(network_address.clone()),
+                type_characters,
+                layout: settings.read().unwrap().layout,
+                ..backend_options.clone()
+            };
+            egui::ComboBox::from_label("Keyboard Output")
+                .selected_text(backend_kind.name())
+                .show_ui(ui, |ui| {
+                    for &kind in BackendKind::ALL {
+                        if ui.selectable_label(kind == backend_kind, kind.name()).on_hover_text(kind.description()).clicked()
+                            && kind != backend_kind
+                            && switch_backend(&keyboard, &settings, kind, &current_options) {
+                            backend_kind = kind;
+                        }
+                    }
+                });
+
+            let type_characters_toggle = ui.checkbox(&mut type_characters, "Type characters")
+                .on_hover_text("Types the characters of the mapping instead of pressing their keys,\nfor web pianos that read the typed character");
+            if type_characters_toggle.clicked() {
+                let options = BackendOptions { type_characters, ..current_options.clone() };
+                if !switch_backend(&keyboard, &settings, backend_kind, &options) {
+                    type_characters = !type_characters;
+                }
+            }
+
+            if let Some(focus) = &focus_guard {
+                ui.horizontal(|ui| {
+                    ui.label("Target window");
+                    ui.text_edit_singleline(&mut target_window)
+                        .on_hover_text("Only send keys while this window is focused: part of its title, or class:<name> / process:<name>.\nLeave empty to send keys to any window");
+                    if ui.button("Apply").clicked() {
+                        match target_window.trim() {
+                            "" => focus.set_filter(None),
+                            pattern => match WindowFilter::parse(pattern) {
+                                Ok(filter) => focus.set_filter(Some(filter)),
+                                Err(err) => log_error!(App, "{err}"),
+                            },
+                        }
+                    }
+                });
+                if focus.filter().is_some() {
+                    let state = if focus.allowed() { "Target focused" } else { "Paused, the target isn't focused" };
+                    let focused = focus.focused_window().map(|window| window.to_string()).unwrap_or("nothing".to_owned());
+                    ui.label(format!("{state}. Focused: {focused}"));
+                    ctx.request_repaint_after(std::time::Duration::from_millis(250));
+                }
+            }
+
+            ui.horizontal(|ui| {
+                ui.label("Forward to");
+                ui.text_edit_singleline(&mut network_address).on_hover_text("host:port of a machine running --key-server, used by the Network output");
+            });
+
+            if cfg!(target_os = "linux") {
+                ui.horizontal(|ui| {
+                    ui.label("Device name");
+                    ui.text_edit_singleline(&mut device_name).on_hover_text("Name of the virtual uinput keyboard, e.g. for blacklisting it in other software");
+                    if backend_kind == BackendKind::Native && ui.button("Apply").clicked() {
+                        switch_backend(&keyboard, &settings, backend_kind, &current_options);
+                    }
+                });
+            }
+
+            ui.collapsing("Playback", |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("File");
+                    ui.text_edit_singleline(&mut midi_file_path).on_hover_text("Path of a .mid file or a sheet (.txt) to play as if it came from the MIDI input");
+                    if ui.button("Play").clicked() {
+                        if let Err(err) = play_file(Path::new(midi_file_path.trim()), &settings, &keyboard, &latency, &command_tx) {
+                            log_error!(App, "{err}");
+                        }
+                    }
+                    if ui.button("Stop").clicked() {
+                        command_tx.send(AppCommand::StopPlayback).expect("Failed to send command");
+                    }
+                });
+                let mut speed = settings.read().unwrap().player.speed();
+                let tempo = egui::Slider::new(&mut speed, Player::MIN_SPEED..=Player::MAX_SPEED).suffix("%").text("Tempo");
+                if ui.add(tempo).on_hover_text("Playback speed, also changing what's playing").changed() {
+                    settings.read().unwrap().player.set_speed(speed);
+                }
+                // Edited on a copy, so MIDI sources aren't kept waiting on the settings while it's drawn
+                let mut timing = settings.read().unwrap().playback;
+                let shown = timing;
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut timing.bpm).clamp_range(20.0..=400.0).suffix(" bpm"))
+                        .on_hover_text("Tempo of exported sheets, the length of a | in them, and of the quantize grid");
+                    if ui.button("Export sheet").on_hover_text("Write the MIDI file as a sheet for the output method, next to it as .txt").clicked() {
+                        let mapping = match settings.read().unwrap().method {
+                            AvailableInputMethod::Generic => Some(sheet::Mapping::Generic),
+                            AvailableInputMethod::PV => Some(sheet::Mapping::Pv),
+                            AvailableInputMethod::PianoRooms => None,
+                        };
+                        match mapping {
+                            Some(mapping) => if let Err(err) = export_sheet(Path::new(midi_file_path.trim()), mapping, timing.bpm) {
+                                log_error!(App, "{err}");
+                            },
+                            None => log_error!(App, "Sheets can only be written for Generic and Piano Visualizations"),
+                        }
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let grid_name = |grid: u32| if grid == 0 { "Off".to_owned() } else { format!("1/{grid}") };
+                    egui::ComboBox::from_label("Quantize")
+                        .selected_text(grid_name(timing.grid))
+                        .show_ui(ui, |ui| {
+                            for grid in std::iter::once(0).chain(Timing::GRIDS) {
+                                ui.selectable_value(&mut timing.grid, grid, grid_name(grid));
+                            }
+                        }).response.on_hover_text("Move notes of played files towards a grid of this note value, at the tempo above");
+                    if timing.grid > 0 {
+                        ui.add(egui::Slider::new(&mut timing.strength, 0.0..=1.0).text("Strength")).on_hover_text("How far notes move towards the grid");
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut timing.jitter).clamp_range(0..=100).prefix("Humanize: ").suffix(" ms"))
+                        .on_hover_text("Shift notes of played files by up to this much at random");
+                    ui.add(egui::DragValue::new(&mut timing.velocity_jitter).clamp_range(0..=40).prefix("Velocity: ±"))
+                        .on_hover_text("Change the velocity of notes by up to this much at random");
+                });
+                if timing != shown {
+                    settings.write().unwrap().playback = timing;
+                }
+                match settings.read().unwrap().player.playing() {
+                    Some(name) => ui.label(format!("Playing {name}")),
+                    None => ui.label("Not playing"),
+                };
+                ui.horizontal(|ui| {
+                    ui.label("Profile");
+                    ui.text_edit_singleline(&mut profile_path).on_hover_text("Path of a profile file with settings to apply");
+                    if ui.button("Load").clicked() {
+                        if let Err(err) = load_profile(Path::new(profile_path.trim()), &settings, &keyboard, &settings_update_tx) {
+                            log_error!(App, "{err}");
+                        }
+                    }
+                });
+                ctx.request_repaint_after(std::time::Duration::from_millis(250));
+            });
+
+            let recorded = keyboard.lock().unwrap().recorded_events();
+            if let Some(recorded) = recorded {
+                ui.collapsing("Dry run", |ui| {
+                    if ui.button("Clear").clicked() {
+                        recorded.lock().unwrap().clear();
+                    }
+                    egui::ScrollArea::vertical().id_source("dry_run").max_height(160.0).stick_to_bottom(true).show(ui, |ui| {
+                        for event in recorded.lock().unwrap().iter() {
+                            ui.label(egui::RichText::new(event.to_string()).monospace());
+                        }
+                    });
+                    ctx.request_repaint_after(std::time::Duration::from_millis(250));
+                });
+            }
+
+            ui.collapsing("Latency", |ui| {
+                let summary = latency.lock().unwrap().summary();
+                ui.label(summary.to_string());
+                ui.horizontal(|ui| {
+                    if ui.button("Reset").clicked() {
+                        latency.lock().unwrap().clear();
+                    }
+                    if ui.button("Export CSV").clicked() {
+                        let path = std::path::Path::new("miditoqwerty-latency.csv");
+                        match latency.lock().unwrap().export_csv(path) {
+                            Ok(()) => log_info!(App, "Exported latency samples to {}", path.display()),
+                            Err(err) => log_error!(App, "Failed to export latency samples: {err}"),
+                        }
+                    }
+                });
+                ctx.request_repaint_after(std::time::Duration::from_millis(250));
+            });
+
+            ui.collapsing("Event log", |ui| {
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_source("log_level")
+                        .selected_text(log_filter.level.name())
+                        .show_ui(ui, |ui| {
+                            for level in event_log::Level::ALL {
+                                ui.selectable_value(&mut log_filter.level, level, level.name());
+                            }
+                        });
+                    // More verbose levels are only recorded from now on
+                    if log_filter.level > event_log::max_level() {
+                        event_log::set_max_level(log_filter.level);
+                    }
+                    if ui.button("Clear").clicked() {
+                        event_log::clear();
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut log_filter.midi_in, event_log::Category::MidiIn.name());
+                    ui.checkbox(&mut log_filter.key_out, event_log::Category::KeyOut.name());
+                    ui.checkbox(&mut log_filter.app, event_log::Category::App.name());
+                });
+
+                egui::ScrollArea::vertical().id_source("event_log").max_height(160.0).stick_to_bottom(true).show(ui, |ui| {
+                    for entry in event_log::snapshot(&log_filter) {
+                        let text = egui::RichText::new(entry.to_string()).monospace();
+                        match entry.level {
+                            event_log::Level::Error => ui.colored_label(egui::Color32::LIGHT_RED, text),
+                            event_log::Level::Warn => ui.colored_label(egui::Color32::YELLOW, text),
+                            _ => ui.label(text),
+                        };
+                    }
+                });
+
+                // New entries arrive from other threads, keep the panel fresh while it's open
+                ctx.request_repaint_after(std::time::Duration::from_millis(250));
+            });
         });
-    })
+    });
+    // The reporter thread keeps the stats alive, so they're never dropped
+    exit_latency.lock().unwrap().flush_csv();
+    event_log::flush();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keyboard_provider::RecordingKeyboard;
+    use keycodes::Key;
+
+    #[test]
+    fn transposing_while_a_note_is_held() {
+        let settings = Arc::new(RwLock::new(Settings::new(AvailableInputMethod::Generic, Layout::Us)));
+        let recorder = RecordingKeyboard::new();
+        let events = recorder.events();
+        let keyboard: SharedKeyboard = Arc::new(Mutex::new(Box::new(recorder)));
+        let latency = Arc::new(Mutex::new(LatencyStats::new()));
+        let (commands, _commands_rx) = mpsc::channel();
+        let (settings_update_tx, _settings_update_rx) = mpsc::channel();
+        let mut pipeline = MidiPipeline::new(&settings, &keyboard, &latency, &commands);
+
+        // C4 is released as D4 after transposing
+        pipeline.process(Instant::now(), &[0x90, 60, 100]);
+        run_command(AppCommand::Transpose(2), &settings, &keyboard, &settings_update_tx);
+        pipeline.process(Instant::now(), &[0x80, 60, 0]);
+        run_command(AppCommand::ResetTranspose, &settings, &keyboard, &settings_update_tx);
+
+        // Its key isn't stuck afterwards
+        events.lock().unwrap().clear();
+        pipeline.process(Instant::now(), &[0x90, 60, 100]);
+        pipeline.process(Instant::now(), &[0x80, 60, 0]);
+        let last = events.lock().unwrap().back().map(|recorded| recorded.event.clone());
+        assert_eq!(last, Some(KeyEvent::Release(Key::parse("t").unwrap())));
+    }
 }
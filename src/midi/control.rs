@@ -0,0 +1,159 @@
+// The control layer: notes, CCs and program changes set aside for commanding the app from the
+// instrument, which never reach the output method.
+
+use super::{CONTROLLER, NOTE_OFF, NOTE_ON, POLY_PRESSURE, PROGRAM_CHANGE};
+use crate::commands::AppCommand;
+
+// CC buttons send 127 when pressed and 0 when released, anything from here on counts as pressed
+const CC_PRESSED: u8 = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlTrigger {
+    Note(u8),
+    Controller(u8),
+    Program(u8),
+}
+
+impl ControlTrigger {
+    // "note:21", "cc:20" or "pc:3"
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (kind, number) = s.split_once(':').ok_or_else(|| format!("Expected note:<n>, cc:<n> or pc:<n>, got {s:?}"))?;
+        let number: u8 = number.trim().parse().ok().filter(|number| *number < 128)
+            .ok_or_else(|| format!("Invalid number in {s:?}, expected 0-127"))?;
+        match kind.trim().to_ascii_lowercase().as_str() {
+            "note" => Ok(ControlTrigger::Note(number)),
+            "cc" => Ok(ControlTrigger::Controller(number)),
+            "pc" => Ok(ControlTrigger::Program(number)),
+            other => Err(format!("Unknown control {other:?}, expected note, cc or pc")),
+        }
+    }
+}
+impl std::fmt::Display for ControlTrigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ControlTrigger::Note(note) => write!(f, "note:{note}"),
+            ControlTrigger::Controller(control) => write!(f, "cc:{control}"),
+            ControlTrigger::Program(program) => write!(f, "pc:{program}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlBinding {
+    pub trigger: ControlTrigger,
+    pub command: AppCommand,
+}
+
+impl ControlBinding {
+    // "cc:20=toggle-output"
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (trigger, command) = s.split_once('=').ok_or_else(|| format!("Expected <control>=<command>, got {s:?}"))?;
+        let command = AppCommand::parse(command.trim())
+            .ok_or_else(|| format!("Unknown command {:?}, expected one of: {}", command.trim(), AppCommand::NAMES))?;
+        Ok(ControlBinding { trigger: ControlTrigger::parse(trigger)?, command })
+    }
+}
+impl std::fmt::Display for ControlBinding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} = {}", self.trigger, self.command)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Control {
+    Pass, // not part of the control layer, goes on to the output method
+    Consumed,
+    Command(AppCommand),
+}
+
+// Bindings of one connection, along with which CC buttons are held
+pub struct ControlLayer {
+    bindings: Vec<ControlBinding>,
+    pressed_controls: [bool; 128],
+}
+
+impl ControlLayer {
+    pub fn new(bindings: Vec<ControlBinding>) -> Self {
+        ControlLayer { bindings, pressed_controls: [false; 128] }
+    }
+
+    fn command(&self, trigger: ControlTrigger) -> Option<AppCommand> {
+        self.bindings.iter().find(|binding| binding.trigger == trigger).map(|binding| binding.command)
+    }
+
+    // With `programs_select_methods`, Program Change n selects the n-th output method unless it's bound
+    pub fn process(&mut self, message: &[u8], programs_select_methods: bool) -> Control {
+        match *message {
+            [status, note, velocity] if status & 0xF0 == NOTE_ON => match self.command(ControlTrigger::Note(note)) {
+                Some(command) if velocity > 0 => Control::Command(command),
+                Some(_) => Control::Consumed,
+                None => Control::Pass,
+            },
+            [status, note, ..] if matches!(status & 0xF0, NOTE_OFF | POLY_PRESSURE) => match self.command(ControlTrigger::Note(note)) {
+                Some(_) => Control::Consumed,
+                None => Control::Pass,
+            },
+            [status, control, value] if status & 0xF0 == CONTROLLER => {
+                let Some(command) = self.command(ControlTrigger::Controller(control)) else {
+                    return Control::Pass;
+                };
+                let pressed = value >= CC_PRESSED;
+                let was_pressed = std::mem::replace(&mut self.pressed_controls[control as usize], pressed);
+                match pressed && !was_pressed {
+                    true => Control::Command(command),
+                    false => Control::Consumed,
+                }
+            }
+            [status, program, ..] if status & 0xF0 == PROGRAM_CHANGE => match self.command(ControlTrigger::Program(program)) {
+                Some(command) => Control::Command(command),
+                None if programs_select_methods => Control::Command(AppCommand::SelectMethod(program)),
+                None => Control::Pass,
+            },
+            _ => Control::Pass,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process_all(layer: &mut ControlLayer, messages: &[&[u8]], programs_select_methods: bool) -> Vec<Control> {
+        messages.iter().map(|message| layer.process(message, programs_select_methods)).collect()
+    }
+
+    #[test]
+    fn parses_bindings() {
+        let binding = ControlBinding::parse("CC:20 = toggle-output").unwrap();
+        assert_eq!(binding.trigger, ControlTrigger::Controller(20));
+        assert_eq!(binding.to_string(), "cc:20 = toggle-output");
+        assert!(ControlBinding::parse("note:128=panic").is_err());
+        assert!(ControlBinding::parse("pitch:1=panic").is_err());
+        assert!(ControlBinding::parse("pc:1").is_err());
+    }
+
+    #[test]
+    fn notes_and_controls() {
+        let bindings = ["note:21=toggle-output", "cc:20=next-method"].map(|binding| ControlBinding::parse(binding).unwrap());
+        let mut layer = ControlLayer::new(bindings.to_vec());
+
+        assert_eq!(process_all(&mut layer, &[&[0x90, 21, 100], &[0x90, 21, 0], &[0x80, 21, 0], &[0x90, 60, 100]], false), vec![
+            Control::Command(AppCommand::ToggleOutput), Control::Consumed, Control::Consumed, Control::Pass,
+        ]);
+        // Only the press of a CC button fires, values in between are swallowed
+        assert_eq!(process_all(&mut layer, &[&[0xB0, 20, 127], &[0xB0, 20, 100], &[0xB0, 20, 0], &[0xB3, 20, 127], &[0xB0, 64, 127]], false), vec![
+            Control::Command(AppCommand::NextMethod), Control::Consumed, Control::Consumed, Control::Command(AppCommand::NextMethod), Control::Pass,
+        ]);
+    }
+
+    #[test]
+    fn program_changes() {
+        let mut layer = ControlLayer::new(vec![ControlBinding::parse("pc:5=transpose-reset").unwrap()]);
+        assert_eq!(process_all(&mut layer, &[&[0xC0, 1], &[0xC0, 5]], true), vec![
+            Control::Command(AppCommand::SelectMethod(1)), Control::Command(AppCommand::ResetTranspose),
+        ]);
+        assert_eq!(process_all(&mut layer, &[&[0xC0, 1], &[0xC0, 5]], false), vec![
+            Control::Pass, Control::Command(AppCommand::ResetTranspose),
+        ]);
+    }
+}
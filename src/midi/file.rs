@@ -0,0 +1,224 @@
+// Reads Standard MIDI Files (format 0 and 1) into channel messages with their time, following
+// tempo changes. Meta events other than tempo and system exclusive messages are left out.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::recording::DEFAULT_TEMPO;
+use super::TimedMessage;
+
+const META: u8 = 0xFF;
+const TEMPO: u8 = 0x51;
+const SYSEX_START: u8 = 0xF0;
+const SYSEX_ESCAPE: u8 = 0xF7;
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], io::Error> {
+        if self.bytes.len() < len {
+            return Err(invalid("Unexpected end of file"));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, io::Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, io::Error> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, io::Error> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn variable_length(&mut self) -> Result<u32, io::Error> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let byte = self.byte()?;
+            value = (value << 7) | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("Variable length quantity longer than four bytes"))
+    }
+}
+
+// How ticks turn into time
+enum Division {
+    PerQuarter(u64),
+    Timecode { micros_per_tick: f64 },
+}
+
+struct TrackEvent {
+    tick: u64,
+    kind: TrackEventKind,
+}
+
+enum TrackEventKind {
+    Tempo(u32),
+    Message(Vec<u8>),
+}
+
+fn read_track(data: &[u8], events: &mut Vec<TrackEvent>) -> Result<(), io::Error> {
+    let mut reader = Reader { bytes: data };
+    let mut tick = 0u64;
+    let mut running_status = None;
+
+    while !reader.bytes.is_empty() {
+        tick += reader.variable_length()? as u64;
+        let first = reader.byte()?;
+        match first {
+            META => {
+                let kind = reader.byte()?;
+                let len = reader.variable_length()? as usize;
+                let data = reader.take(len)?;
+                if kind == TEMPO && len == 3 {
+                    let tempo = u32::from_be_bytes([0, data[0], data[1], data[2]]);
+                    events.push(TrackEvent { tick, kind: TrackEventKind::Tempo(tempo) });
+                }
+            }
+            SYSEX_START | SYSEX_ESCAPE => {
+                let len = reader.variable_length()? as usize;
+                reader.take(len)?;
+            }
+            _ => {
+                let status = match first {
+                    0x80..=0xEF => {
+                        running_status = Some(first);
+                        first
+                    }
+                    _ => running_status.ok_or_else(|| invalid(format!("Data byte {first:#04x} without a status")))?,
+                };
+                let mut message = vec![status];
+                if first < 0x80 {
+                    message.push(first);
+                }
+                let len = if matches!(status & 0xF0, 0xC0 | 0xD0) { 2 } else { 3 };
+                while message.len() < len {
+                    message.push(reader.byte()?);
+                }
+                events.push(TrackEvent { tick, kind: TrackEventKind::Message(message) });
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn read_smf(bytes: &[u8]) -> Result<Vec<TimedMessage>, io::Error> {
+    let mut reader = Reader { bytes };
+    if reader.take(4)? != b"MThd" {
+        return Err(invalid("Not a MIDI file"));
+    }
+    let header_len = reader.u32()? as usize;
+    let mut header = Reader { bytes: reader.take(header_len)? };
+    let format = header.u16()?;
+    let _tracks = header.u16()?;
+    let division = header.u16()?;
+    if format > 1 {
+        return Err(invalid(format!("MIDI file format {format} isn't supported, only 0 and 1")));
+    }
+
+    let division = match division & 0x8000 {
+        0 => Division::PerQuarter(division.max(1) as u64),
+        _ => {
+            let frames_per_second = -((division >> 8) as i8) as f64;
+            let ticks_per_frame = (division & 0xFF) as f64;
+            Division::Timecode { micros_per_tick: 1_000_000.0 / (frames_per_second * ticks_per_frame).max(1.0) }
+        }
+    };
+
+    let mut events = vec![];
+    while !reader.bytes.is_empty() {
+        let kind = reader.take(4)?;
+        let len = reader.u32()? as usize;
+        let data = reader.take(len)?;
+        // Other chunk types are allowed and meant to be skipped
+        if kind == b"MTrk" {
+            read_track(data, &mut events)?;
+        }
+    }
+    // Stable, so events of a track keep their order and format 1 tracks merge in track order
+    events.sort_by_key(|event| event.tick);
+
+    let mut messages = vec![];
+    let (mut tempo, mut tempo_tick, mut tempo_time) = (DEFAULT_TEMPO as u64, 0u64, 0u64);
+    for event in events {
+        let time = match division {
+            Division::PerQuarter(ticks_per_quarter) => tempo_time + (event.tick - tempo_tick) * tempo / ticks_per_quarter,
+            Division::Timecode { micros_per_tick } => (event.tick as f64 * micros_per_tick) as u64,
+        };
+        match event.kind {
+            TrackEventKind::Tempo(new_tempo) => {
+                (tempo, tempo_tick, tempo_time) = (new_tempo as u64, event.tick, time);
+            }
+            TrackEventKind::Message(message) => messages.push(TimedMessage { time, message }),
+        }
+    }
+    Ok(messages)
+}
+
+pub fn read_file(path: &Path) -> Result<Vec<TimedMessage>, io::Error> {
+    read_smf(&fs::read(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::recording::smf_bytes;
+
+    fn track(events: &[u8]) -> Vec<u8> {
+        let mut chunk = b"MTrk".to_vec();
+        chunk.extend((events.len() as u32).to_be_bytes());
+        chunk.extend(events);
+        chunk
+    }
+
+    #[test]
+    fn reads_recordings_back() {
+        let recorded = vec![
+            TimedMessage { time: 0, message: vec![0x90, 60, 100] },
+            TimedMessage { time: 250_000, message: vec![0xB0, 64, 127] },
+            TimedMessage { time: 500_000, message: vec![0x80, 60, 0] },
+            TimedMessage { time: 1_000_000, message: vec![0xC0, 3] },
+        ];
+        assert_eq!(read_smf(&smf_bytes(&recorded)).unwrap(), recorded);
+    }
+
+    #[test]
+    fn follows_tempo_across_tracks() {
+        // Format 1, 96 ticks per quarter: a tempo track doubling the speed at tick 96, notes with running status
+        let mut bytes = b"MThd".to_vec();
+        bytes.extend([0, 0, 0, 6, 0, 1, 0, 2, 0, 96]);
+        bytes.extend(track(&[0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20, 0x60, 0xFF, 0x51, 0x03, 0x03, 0xD0, 0x90, 0x00, 0xFF, 0x2F, 0x00]));
+        bytes.extend(track(&[0x00, 0xF0, 0x02, 0x01, 0xF7, 0x60, 0x90, 60, 100, 0x60, 60, 0, 0x00, 0xFF, 0x2F, 0x00]));
+        bytes.extend(b"XTRA\0\0\0\x01\0");
+
+        assert_eq!(read_smf(&bytes).unwrap(), vec![
+            TimedMessage { time: 500_000, message: vec![0x90, 60, 100] },
+            TimedMessage { time: 750_000, message: vec![0x90, 60, 0] },
+        ]);
+    }
+
+    #[test]
+    fn rejects_broken_files() {
+        assert!(read_smf(b"RIFF").is_err());
+        assert!(read_smf(&[b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 2, 0, 1, 0, 96]).is_err());
+        let mut truncated = b"MThd".to_vec();
+        truncated.extend([0, 0, 0, 6, 0, 0, 0, 1, 0, 96]);
+        truncated.extend(track(&[0x00, 0x90, 60]));
+        assert!(read_smf(&truncated).is_err());
+    }
+}
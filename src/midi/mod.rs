@@ -0,0 +1,125 @@
+// Helpers working on raw MIDI messages, before they're parsed
+
+use std::borrow::Cow;
+
+mod control;
+mod file;
+mod network;
+mod player;
+mod recording;
+mod thru;
+mod timing;
+
+pub use control::{Control, ControlBinding, ControlLayer};
+pub use file::read_file;
+pub use network::{listen_rtp_midi, listen_udp};
+pub use player::Player;
+pub use recording::Recorder;
+pub use thru::{Thru, ThruTarget};
+pub use timing::{apply as apply_timing, Timing};
+
+const NOTE_OFF: u8 = 0x80;
+const NOTE_ON: u8 = 0x90;
+const POLY_PRESSURE: u8 = 0xA0;
+const CONTROLLER: u8 = 0xB0;
+const PROGRAM_CHANGE: u8 = 0xC0;
+
+const SUSTAIN: u8 = 64;
+const SOSTENUTO: u8 = 66;
+const PEDAL_DOWN: u8 = 64;
+
+pub const MAX_TRANSPOSE: i8 = 48;
+
+// A message and when it happens, in microseconds from the start of a file or recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedMessage {
+    pub time: u64,
+    pub message: Vec<u8>,
+}
+
+// Notes and pedals a source is holding, to show them or release them when it goes away mid-song
+#[derive(Debug, Default, Clone)]
+pub struct NoteState {
+    held: Vec<(u8, u8)>, // channel, note
+    pub sustain: bool,
+    pub sostenuto: bool,
+}
+
+impl NoteState {
+    pub fn track(&mut self, message: &[u8]) {
+        match *message {
+            [status, note, velocity] if matches!(status & 0xF0, NOTE_ON | NOTE_OFF) => {
+                let channel = status & 0x0F;
+                self.held.retain(|&held| held != (channel, note));
+                if status & 0xF0 == NOTE_ON && velocity > 0 {
+                    self.held.push((channel, note));
+                }
+            }
+            [status, SUSTAIN, value] if status & 0xF0 == CONTROLLER => self.sustain = value >= PEDAL_DOWN,
+            [status, SOSTENUTO, value] if status & 0xF0 == CONTROLLER => self.sostenuto = value >= PEDAL_DOWN,
+            _ => {}
+        }
+    }
+
+    // Held notes from low to high, whatever channel they're on
+    pub fn notes(&self) -> Vec<u8> {
+        let mut notes: Vec<u8> = self.held.iter().map(|&(_, note)| note).collect();
+        notes.sort_unstable();
+        notes.dedup();
+        notes
+    }
+
+    // Note offs and pedal releases for everything held, which is forgotten
+    pub fn releases(&mut self) -> Vec<Vec<u8>> {
+        let mut releases: Vec<Vec<u8>> = self.held.drain(..).map(|(channel, note)| vec![NOTE_OFF | channel, note, 0]).collect();
+        if std::mem::take(&mut self.sustain) {
+            releases.push(vec![CONTROLLER, SUSTAIN, 0]);
+        }
+        if std::mem::take(&mut self.sostenuto) {
+            releases.push(vec![CONTROLLER, SOSTENUTO, 0]);
+        }
+        releases
+    }
+}
+
+// Shifts the note of note messages by `semitones`, None when it falls outside of 0-127
+pub fn transpose(message: &[u8], semitones: i8) -> Option<Cow<'_, [u8]>> {
+    match message {
+        [status, note, ..] if semitones != 0 && matches!(status & 0xF0, NOTE_OFF | NOTE_ON | POLY_PRESSURE) => {
+            let note = *note as i16 + semitones as i16;
+            if !(0..=127).contains(&note) {
+                return None;
+            }
+            let mut transposed = message.to_vec();
+            transposed[1] = note as u8;
+            Some(Cow::Owned(transposed))
+        }
+        _ => Some(Cow::Borrowed(message)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transposes_notes_only() {
+        assert_eq!(transpose(&[0x90, 60, 100], 12).as_deref(), Some(&[0x90, 72, 100][..]));
+        assert_eq!(transpose(&[0x83, 60, 0], -1).as_deref(), Some(&[0x83, 59, 0][..]));
+        assert_eq!(transpose(&[0xB0, 64, 127], 12).as_deref(), Some(&[0xB0, 64, 127][..]));
+        assert_eq!(transpose(&[0x90, 120, 100], 12), None);
+        assert_eq!(transpose(&[0x90, 3, 100], -4), None);
+    }
+
+    #[test]
+    fn tracks_notes_and_pedals() {
+        let mut state = NoteState::default();
+        for message in [[0x90, 64, 100], [0x91, 60, 100], [0xB0, 64, 127], [0x90, 62, 100], [0x90, 62, 0]] {
+            state.track(&message);
+        }
+        assert_eq!(state.notes(), vec![60, 64]);
+        assert!(state.sustain && !state.sostenuto);
+        assert_eq!(state.releases(), vec![vec![0x80, 64, 0], vec![0x81, 60, 0], vec![0xB0, 64, 0]]);
+        assert!(state.notes().is_empty() && !state.sustain);
+    }
+}
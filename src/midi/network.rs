@@ -0,0 +1,369 @@
+// Receives MIDI over the network, to play a game on one machine from a keyboard plugged into another.
+//
+// Plain UDP: every datagram holds one or more complete MIDI messages, running status allowed,
+// e.g. `90 3c 64` or `90 3c 64 3e 64`. System exclusive messages are skipped.
+//
+// RTP-MIDI (AppleMIDI, RFC 6295): sessions are accepted on a control port and the data port after
+// it, as offered by macOS' Audio MIDI Setup or rtpMIDI on Windows. Messages are played as they
+// arrive, their delta times and the recovery journal are ignored.
+
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use super::NoteState;
+
+const SYSEX_START: u8 = 0xF0;
+const SYSEX_END: u8 = 0xF7;
+
+const APPLEMIDI_SIGNATURE: [u8; 2] = [0xFF, 0xFF];
+const APPLEMIDI_VERSION: u32 = 2;
+const SESSION_NAME: &str = "miditoqwerty";
+const RTP_HEADER_LEN: usize = 12;
+const MAX_PACKET: usize = 1500;
+
+// Length of a message starting with `status`, None for sysex, which runs until SYSEX_END
+fn message_length(status: u8) -> Option<usize> {
+    match status {
+        0x80..=0xBF | 0xE0..=0xEF | 0xF2 => Some(3),
+        0xC0..=0xDF | 0xF1 | 0xF3 => Some(2),
+        SYSEX_START => None,
+        _ => Some(1),
+    }
+}
+
+// Reads the message at the start of `bytes`, returning it (None when skipped) and the bytes it took.
+// Only channel messages set the running status, which lets a data byte start a message.
+fn read_message(bytes: &[u8], running_status: &mut Option<u8>) -> Option<(Option<Vec<u8>>, usize)> {
+    let first = *bytes.first()?;
+    if first == SYSEX_START {
+        let end = bytes.iter().position(|&byte| byte == SYSEX_END).map_or(bytes.len(), |end| end + 1);
+        return Some((None, end));
+    }
+
+    let (status, data) = match first {
+        0x80..=0xFF => (first, &bytes[1..]),
+        _ => match *running_status {
+            Some(status) => (status, bytes),
+            None => return Some((None, 1)), // stray data byte
+        },
+    };
+    if status < 0xF0 {
+        *running_status = Some(status);
+    }
+
+    let data_len = message_length(status).unwrap_or(1) - 1;
+    if data.len() < data_len || data[..data_len].iter().any(|&byte| byte >= 0x80) {
+        // Truncated, skip to the next status byte
+        let skipped = bytes.len() - data.len() + data.iter().position(|&byte| byte >= 0x80).unwrap_or(data.len());
+        return Some((None, skipped.max(1)));
+    }
+
+    let mut message = vec![status];
+    message.extend(&data[..data_len]);
+    Some((Some(message), bytes.len() - data.len() + data_len))
+}
+
+pub fn parse_datagram(mut bytes: &[u8]) -> Vec<Vec<u8>> {
+    let mut running_status = None;
+    let mut messages = vec![];
+    while let Some((message, used)) = read_message(bytes, &mut running_status) {
+        messages.extend(message);
+        bytes = &bytes[used..];
+    }
+    messages
+}
+
+// The MIDI command section of an RTP-MIDI packet, after the RTP header
+pub fn parse_rtp_midi(payload: &[u8]) -> Vec<Vec<u8>> {
+    let Some(&flags) = payload.first() else { return vec![] };
+    let long_header = flags & 0x80 != 0;
+    let first_has_delta = flags & 0x20 != 0;
+
+    let (len, header_len) = match long_header {
+        true if payload.len() >= 2 => ((((flags & 0x0F) as usize) << 8) | payload[1] as usize, 2),
+        true => return vec![],
+        false => ((flags & 0x0F) as usize, 1),
+    };
+    let mut list = &payload[header_len..(header_len + len).min(payload.len())];
+
+    let mut running_status = None;
+    let mut messages = vec![];
+    let mut skip_delta = !first_has_delta;
+    while !list.is_empty() {
+        if !skip_delta {
+            // Delta time, up to four bytes with the top bit marking continuation
+            let delta_len = list.iter().take(4).position(|&byte| byte & 0x80 == 0).map_or(4, |last| last + 1);
+            list = &list[delta_len.min(list.len())..];
+        }
+        skip_delta = false;
+
+        let Some((message, used)) = read_message(list, &mut running_status) else { break };
+        messages.extend(message);
+        list = &list[used..];
+    }
+    messages
+}
+
+type OnMessage = Box<dyn FnMut(&[u8]) + Send>;
+
+// Plays the messages of one source through `on_message`, keeping track of what it holds
+struct Receiver {
+    on_message: OnMessage,
+    held: NoteState,
+}
+
+impl Receiver {
+    fn receive(&mut self, messages: Vec<Vec<u8>>) {
+        for message in messages {
+            log_trace!(MidiIn, "Network message: {:?}", message);
+            self.held.track(&message);
+            (self.on_message)(&message);
+        }
+    }
+
+    fn release_held(&mut self) {
+        for message in self.held.releases() {
+            (self.on_message)(&message);
+        }
+    }
+}
+
+// Listens for plain UDP datagrams on `address`, returning the address actually bound
+pub fn listen_udp(address: &str, on_message: impl FnMut(&[u8]) + Send + 'static) -> Result<SocketAddr, io::Error> {
+    let socket = UdpSocket::bind(address)?;
+    let local = socket.local_addr()?;
+    let mut receiver = Receiver { on_message: Box::new(on_message), held: NoteState::default() };
+
+    thread::spawn(move || {
+        let mut buf = [0u8; MAX_PACKET];
+        loop {
+            match socket.recv_from(&mut buf) {
+                Ok((len, _)) => receiver.receive(parse_datagram(&buf[..len])),
+                Err(err) => {
+                    log_error!(MidiIn, "Network MIDI on {} stopped: {}", local, err);
+                    return;
+                }
+            }
+        }
+    });
+    Ok(local)
+}
+
+struct Session {
+    receiver: Receiver,
+    ssrc: u32,
+    started: Instant,
+    peer: Option<(u32, String)>, // SSRC and name of the connected initiator
+}
+
+impl Session {
+    // 100 microsecond units, as AppleMIDI clock sync wants them
+    fn timestamp(&self) -> u64 {
+        (self.started.elapsed().as_micros() / 100) as u64
+    }
+
+    // Handles one packet, returning the reply to send back
+    fn handle(&mut self, packet: &[u8]) -> Option<Vec<u8>> {
+        if packet.starts_with(&APPLEMIDI_SIGNATURE) {
+            return self.handle_command(packet);
+        }
+        // RTP version 2, payload after the fixed header
+        if packet.len() > RTP_HEADER_LEN && packet[0] >> 6 == 2 {
+            self.receiver.receive(parse_rtp_midi(&packet[RTP_HEADER_LEN..]));
+        }
+        None
+    }
+
+    fn handle_command(&mut self, packet: &[u8]) -> Option<Vec<u8>> {
+        let word = |at: usize| packet.get(at..at + 4).map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()));
+        match packet.get(2..4)? {
+            b"IN" => {
+                let token = word(8)?;
+                let ssrc = word(12)?;
+                let name = packet.get(16..).map(|name| String::from_utf8_lossy(name).trim_end_matches('\0').to_owned()).unwrap_or_default();
+                if self.peer.as_ref().map(|(peer, _)| *peer) != Some(ssrc) {
+                    log_info!(App, "RTP-MIDI session from {:?}", name);
+                }
+                self.peer = Some((ssrc, name));
+
+                let mut reply = APPLEMIDI_SIGNATURE.to_vec();
+                reply.extend(b"OK");
+                reply.extend(APPLEMIDI_VERSION.to_be_bytes());
+                reply.extend(token.to_be_bytes());
+                reply.extend(self.ssrc.to_be_bytes());
+                reply.extend(SESSION_NAME.as_bytes());
+                reply.push(0);
+                Some(reply)
+            }
+            b"CK" => {
+                // The initiator sends count 0, we answer with count 1 and our time, it finishes with count 2
+                if *packet.get(8)? != 0 || packet.len() < 36 {
+                    return None;
+                }
+                let mut reply = APPLEMIDI_SIGNATURE.to_vec();
+                reply.extend(b"CK");
+                reply.extend(self.ssrc.to_be_bytes());
+                reply.extend([1, 0, 0, 0]);
+                reply.extend(&packet[12..20]);
+                reply.extend(self.timestamp().to_be_bytes());
+                reply.extend(0u64.to_be_bytes());
+                Some(reply)
+            }
+            b"BY" => {
+                if let Some((_, name)) = self.peer.take() {
+                    log_info!(App, "RTP-MIDI session from {:?} ended", name);
+                }
+                self.receiver.release_held();
+                None
+            }
+            _ => None, // receiver feedback and the like
+        }
+    }
+}
+
+// Runs an RTP-MIDI session on a control socket and the data socket next to it
+fn serve_rtp_midi(control: UdpSocket, data: UdpSocket, on_message: OnMessage) -> Result<(), io::Error> {
+    let ssrc = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.subsec_nanos()).unwrap_or_default() ^ std::process::id();
+    let session = Arc::new(Mutex::new(Session {
+        receiver: Receiver { on_message, held: NoteState::default() },
+        ssrc,
+        started: Instant::now(),
+        peer: None,
+    }));
+
+    for socket in [control, data] {
+        let session = Arc::clone(&session);
+        let local = socket.local_addr()?;
+        thread::spawn(move || {
+            let mut buf = [0u8; MAX_PACKET];
+            loop {
+                let (len, from) = match socket.recv_from(&mut buf) {
+                    Ok(received) => received,
+                    Err(err) => {
+                        log_error!(MidiIn, "RTP-MIDI on {} stopped: {}", local, err);
+                        return;
+                    }
+                };
+                let reply = session.lock().unwrap().handle(&buf[..len]);
+                if let Some(reply) = reply {
+                    if let Err(err) = socket.send_to(&reply, from) {
+                        log_warn!(MidiIn, "Failed to answer RTP-MIDI peer {}: {}", from, err);
+                    }
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+// Accepts RTP-MIDI sessions with the control port at `address` and the data port after it
+pub fn listen_rtp_midi(address: &str, on_message: impl FnMut(&[u8]) + Send + 'static) -> Result<SocketAddr, io::Error> {
+    let control = UdpSocket::bind(address)?;
+    let mut data_address = control.local_addr()?;
+    data_address.set_port(data_address.port().checked_add(1).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No port after the control port"))?);
+    let data = UdpSocket::bind(data_address)?;
+
+    let local = control.local_addr()?;
+    serve_rtp_midi(control, data, Box::new(on_message))?;
+    Ok(local)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn parses_datagrams() {
+        assert_eq!(parse_datagram(&[0x90, 60, 100, 62, 100, 0xB0, 64, 127]), vec![vec![0x90, 60, 100], vec![0x90, 62, 100], vec![0xB0, 64, 127]]);
+        assert_eq!(parse_datagram(&[0xF0, 1, 2, 0xF7, 0xC0, 5, 0xF8]), vec![vec![0xC0, 5], vec![0xF8]]);
+        // A truncated message doesn't take the next one with it
+        assert_eq!(parse_datagram(&[0x90, 60, 0x80, 60, 0]), vec![vec![0x80, 60, 0]]);
+        assert_eq!(parse_datagram(&[60, 100]), Vec::<Vec<u8>>::new());
+    }
+
+    #[test]
+    fn parses_rtp_midi_commands() {
+        // Short header, no delta before the first command, running status after a delta
+        assert_eq!(parse_rtp_midi(&[0x06, 0x90, 60, 100, 0x00, 62, 100]), vec![vec![0x90, 60, 100], vec![0x90, 62, 100]]);
+        // Long header, Z flag and a two byte delta
+        assert_eq!(parse_rtp_midi(&[0xA0, 0x08, 0x81, 0x00, 0x80, 60, 0, 0x00, 0xC0, 3]), vec![vec![0x80, 60, 0], vec![0xC0, 3]]);
+        // The list ends where the header says, whatever follows is the journal
+        assert_eq!(parse_rtp_midi(&[0x43, 0x90, 60, 100, 0x01, 0x02]), vec![vec![0x90, 60, 100]]);
+    }
+
+    fn receive_all(rx: &mpsc::Receiver<Vec<u8>>, count: usize) -> Vec<Vec<u8>> {
+        (0..count).map(|_| rx.recv_timeout(Duration::from_secs(2)).expect("Message didn't arrive")).collect()
+    }
+
+    #[test]
+    fn udp_loopback() {
+        let (tx, rx) = mpsc::channel();
+        let address = listen_udp("127.0.0.1:0", move |message| tx.send(message.to_vec()).unwrap()).unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(&[0x90, 60, 100, 0x80, 60, 0], address).unwrap();
+        assert_eq!(receive_all(&rx, 2), vec![vec![0x90, 60, 100], vec![0x80, 60, 0]]);
+    }
+
+    #[test]
+    fn rtp_midi_session() {
+        let (tx, rx) = mpsc::channel();
+        let control = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let data = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (control_address, data_address) = (control.local_addr().unwrap(), data.local_addr().unwrap());
+        serve_rtp_midi(control, data, Box::new(move |message: &[u8]| tx.send(message.to_vec()).unwrap())).unwrap();
+
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        peer.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut buf = [0u8; MAX_PACKET];
+
+        // Invitation on both ports
+        let mut invitation = APPLEMIDI_SIGNATURE.to_vec();
+        invitation.extend(b"IN");
+        invitation.extend(2u32.to_be_bytes());
+        invitation.extend(0x1234u32.to_be_bytes());
+        invitation.extend(0xCAFEu32.to_be_bytes());
+        invitation.extend(b"test\0");
+        for address in [control_address, data_address] {
+            peer.send_to(&invitation, address).unwrap();
+            let len = peer.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..4], &[0xFF, 0xFF, b'O', b'K']);
+            assert_eq!(&buf[8..12], &0x1234u32.to_be_bytes());
+            assert_eq!(&buf[16..len], b"miditoqwerty\0");
+        }
+
+        // Clock sync
+        let mut sync = APPLEMIDI_SIGNATURE.to_vec();
+        sync.extend(b"CK");
+        sync.extend(0xCAFEu32.to_be_bytes());
+        sync.extend([0, 0, 0, 0]);
+        sync.extend(42u64.to_be_bytes());
+        sync.extend([0; 16]);
+        peer.send_to(&sync, data_address).unwrap();
+        let len = peer.recv(&mut buf).unwrap();
+        assert_eq!(len, 36);
+        assert_eq!(buf[8], 1);
+        assert_eq!(&buf[12..20], &42u64.to_be_bytes());
+
+        // Two note ons, the second by running status, then the session ends with both held
+        let mut packet = vec![0x80, 0x61, 0, 1, 0, 0, 0, 0];
+        packet.extend(0xCAFEu32.to_be_bytes());
+        packet.extend([0x06, 0x90, 60, 100, 0x00, 64, 90]);
+        peer.send_to(&packet, data_address).unwrap();
+        assert_eq!(receive_all(&rx, 2), vec![vec![0x90, 60, 100], vec![0x90, 64, 90]]);
+
+        let mut bye = APPLEMIDI_SIGNATURE.to_vec();
+        bye.extend(b"BY");
+        bye.extend(2u32.to_be_bytes());
+        bye.extend(0x1234u32.to_be_bytes());
+        bye.extend(0xCAFEu32.to_be_bytes());
+        peer.send_to(&bye, control_address).unwrap();
+        assert_eq!(receive_all(&rx, 2), vec![vec![0x80, 60, 0], vec![0x80, 64, 0]]);
+    }
+}
@@ -0,0 +1,175 @@
+// Plays timed messages (a MIDI file, a recording) in the background, as if they came from an instrument
+
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::{NoteState, TimedMessage};
+
+// Longest sleep between checks for being stopped or a new speed
+const STOP_CHECK: Duration = Duration::from_millis(20);
+
+struct Playback {
+    name: String,
+    stop: Arc<AtomicBool>,
+}
+
+pub struct Player {
+    current: Arc<Mutex<Option<Playback>>>,
+    thread: Mutex<Option<JoinHandle<()>>>, // the last playback, which may still be releasing its notes
+    speed: Arc<AtomicU32>, // percent, also changing what's playing
+}
+impl Default for Player {
+    fn default() -> Self {
+        Player { current: Arc::default(), thread: Mutex::default(), speed: Arc::new(AtomicU32::new(100)) }
+    }
+}
+
+impl Player {
+    pub const MIN_SPEED: u32 = 10;
+    pub const MAX_SPEED: u32 = 400;
+
+    // Name of what's playing
+    pub fn playing(&self) -> Option<String> {
+        self.current.lock().unwrap().as_ref().map(|playback| playback.name.clone())
+    }
+
+    pub fn speed(&self) -> u32 {
+        self.speed.load(Ordering::Relaxed)
+    }
+
+    pub fn set_speed(&self, percent: u32) {
+        self.speed.store(percent.clamp(Self::MIN_SPEED, Self::MAX_SPEED), Ordering::Relaxed);
+    }
+
+    // Stops whatever was playing and starts `messages`, releasing held notes when done or stopped
+    pub fn play(&self, name: String, messages: Vec<TimedMessage>, mut on_message: impl FnMut(&[u8]) + Send + 'static) {
+        self.stop();
+        // Its releases would cut off the same notes pressed by the new one
+        let mut thread = self.thread.lock().unwrap();
+        if let Some(previous) = thread.take() {
+            let _ = previous.join();
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        *self.current.lock().unwrap() = Some(Playback { name: name.clone(), stop: Arc::clone(&stop) });
+        let current = Arc::clone(&self.current);
+        let speed = Arc::clone(&self.speed);
+        log_info!(App, "Playing {}", name);
+
+        *thread = Some(thread::spawn(move || {
+            // Where in `messages` playback is, moving faster or slower than the clock with the speed
+            let mut position = Duration::ZERO;
+            let mut last = Instant::now();
+            let mut notes = NoteState::default();
+            for TimedMessage { time, message } in messages {
+                let due = Duration::from_micros(time);
+                while position < due {
+                    if stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let speed = speed.load(Ordering::Relaxed) as f64 / 100.0;
+                    thread::sleep((due - position).div_f64(speed).min(STOP_CHECK));
+                    let now = Instant::now();
+                    position += (now - last).mul_f64(speed);
+                    last = now;
+                }
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                notes.track(&message);
+                on_message(&message);
+            }
+
+            for release in notes.releases() {
+                on_message(&release);
+            }
+            let mut current = current.lock().unwrap();
+            if current.as_ref().is_some_and(|playback| Arc::ptr_eq(&playback.stop, &stop)) {
+                *current = None;
+                log_info!(App, "Finished playing {}", name);
+            }
+        }));
+    }
+
+    pub fn stop(&self) {
+        if let Some(playback) = self.current.lock().unwrap().take() {
+            playback.stop.store(true, Ordering::Relaxed);
+            log_info!(App, "Stopped playing {}", playback.name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    #[test]
+    fn plays_in_time_and_releases_on_stop() {
+        let (tx, rx) = mpsc::channel();
+        let player = Player::default();
+        let messages = vec![
+            TimedMessage { time: 0, message: vec![0x90, 60, 100] },
+            TimedMessage { time: 50_000, message: vec![0x90, 64, 100] },
+            TimedMessage { time: 10_000_000, message: vec![0x90, 67, 100] },
+        ];
+        let start = Instant::now();
+        player.play("test".to_owned(), messages, move |message| tx.send((start.elapsed(), message.to_vec())).unwrap());
+        assert_eq!(player.playing().as_deref(), Some("test"));
+
+        let (_, first) = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        let (at, second) = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!((first, second), (vec![0x90, 60, 100], vec![0x90, 64, 100]));
+        assert!(at >= Duration::from_millis(50));
+
+        player.stop();
+        assert_eq!(player.playing(), None);
+        let released: Vec<Vec<u8>> = (0..2).map(|_| rx.recv_timeout(Duration::from_secs(1)).unwrap().1).collect();
+        assert_eq!(released, vec![vec![0x80, 60, 0], vec![0x80, 64, 0]]);
+    }
+
+    #[test]
+    fn playing_again_releases_first() {
+        let (tx, rx) = mpsc::channel();
+        let player = Player::default();
+        let first = vec![
+            TimedMessage { time: 0, message: vec![0x90, 60, 100] },
+            TimedMessage { time: 10_000_000, message: vec![0x90, 62, 100] },
+        ];
+        let second = vec![
+            TimedMessage { time: 0, message: vec![0x90, 60, 100] },
+            TimedMessage { time: 100_000, message: vec![0x80, 60, 0] },
+        ];
+        let sent = tx.clone();
+        player.play("first".to_owned(), first, move |message| sent.send(message.to_vec()).unwrap());
+        rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        player.play("second".to_owned(), second, move |message| tx.send(message.to_vec()).unwrap());
+
+        let messages: Vec<Vec<u8>> = (0..3).map(|_| rx.recv_timeout(Duration::from_secs(1)).unwrap()).collect();
+        assert_eq!(messages, vec![vec![0x80, 60, 0], vec![0x90, 60, 100], vec![0x80, 60, 0]]);
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn follows_the_speed() {
+        let (tx, rx) = mpsc::channel();
+        let player = Player::default();
+        player.set_speed(400);
+        let messages = vec![
+            TimedMessage { time: 0, message: vec![0x90, 60, 100] },
+            TimedMessage { time: 400_000, message: vec![0x80, 60, 0] },
+        ];
+        let start = Instant::now();
+        player.play("test".to_owned(), messages, move |_| tx.send(start.elapsed()).unwrap());
+
+        rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        let at = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert!(at >= Duration::from_millis(100) && at < Duration::from_millis(300), "{at:?}");
+        assert_eq!(player.speed(), 400);
+        player.set_speed(1000);
+        assert_eq!(player.speed(), Player::MAX_SPEED);
+    }
+}
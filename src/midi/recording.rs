@@ -0,0 +1,119 @@
+// Records incoming channel messages and saves them as a type 0 Standard MIDI File
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use super::TimedMessage;
+
+pub const TICKS_PER_QUARTER: u16 = 480;
+pub const DEFAULT_TEMPO: u32 = 500_000; // microseconds per quarter note, 120 BPM
+
+struct Recording {
+    start: Instant,
+    messages: Vec<TimedMessage>,
+}
+
+#[derive(Default)]
+pub struct Recorder {
+    current: Mutex<Option<Recording>>,
+}
+
+impl Recorder {
+    pub fn is_recording(&self) -> bool {
+        self.current.lock().unwrap().is_some()
+    }
+
+    pub fn start(&self) {
+        *self.current.lock().unwrap() = Some(Recording { start: Instant::now(), messages: vec![] });
+    }
+
+    pub fn record(&self, message: &[u8]) {
+        // System messages (clock, sysex, ...) aren't part of the performance
+        if !matches!(message.first(), Some(0x80..=0xEF)) {
+            return;
+        }
+        if let Some(recording) = self.current.lock().unwrap().as_mut() {
+            recording.messages.push(TimedMessage { time: recording.start.elapsed().as_micros() as u64, message: message.to_vec() });
+        }
+    }
+
+    // Stops and writes what was recorded to a new file in `dir`, None when nothing was being recorded
+    pub fn stop(&self, dir: &Path) -> Option<Result<PathBuf, io::Error>> {
+        let recording = self.current.lock().unwrap().take()?;
+        let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or_default();
+        let path = dir.join(format!("miditoqwerty-recording-{seconds}.mid"));
+        Some(fs::write(&path, smf_bytes(&recording.messages)).map(|()| path))
+    }
+}
+
+fn write_variable_length(bytes: &mut Vec<u8>, mut value: u32) {
+    let mut groups = vec![(value & 0x7F) as u8];
+    value >>= 7;
+    while value > 0 {
+        groups.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.extend(groups.iter().rev());
+}
+
+pub fn smf_bytes(messages: &[TimedMessage]) -> Vec<u8> {
+    let mut track = vec![];
+    // Tempo meta event, so ticks map back to the recorded times
+    write_variable_length(&mut track, 0);
+    track.extend([0xFF, 0x51, 0x03]);
+    track.extend(&DEFAULT_TEMPO.to_be_bytes()[1..]);
+
+    let mut last_tick = 0;
+    for TimedMessage { time, message } in messages {
+        let tick = time * TICKS_PER_QUARTER as u64 / DEFAULT_TEMPO as u64;
+        write_variable_length(&mut track, (tick - last_tick) as u32);
+        track.extend(message);
+        last_tick = tick;
+    }
+    write_variable_length(&mut track, 0);
+    track.extend([0xFF, 0x2F, 0x00]);
+
+    let mut bytes = b"MThd".to_vec();
+    bytes.extend(6u32.to_be_bytes());
+    bytes.extend(0u16.to_be_bytes()); // format 0, one track
+    bytes.extend(1u16.to_be_bytes());
+    bytes.extend(TICKS_PER_QUARTER.to_be_bytes());
+    bytes.extend(b"MTrk");
+    bytes.extend((track.len() as u32).to_be_bytes());
+    bytes.extend(track);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variable_length() {
+        for (value, expected) in [(0, &[0x00][..]), (0x7F, &[0x7F]), (0x80, &[0x81, 0x00]), (0x3FFF, &[0xFF, 0x7F]), (0x200000, &[0x81, 0x80, 0x80, 0x00])] {
+            let mut bytes = vec![];
+            write_variable_length(&mut bytes, value);
+            assert_eq!(bytes, expected);
+        }
+    }
+
+    #[test]
+    fn writes_smf() {
+        // A quarter note at 120 BPM lasts half a second, or 480 ticks
+        let bytes = smf_bytes(&[
+            TimedMessage { time: 0, message: vec![0x90, 60, 100] },
+            TimedMessage { time: 500_000, message: vec![0x80, 60, 0] },
+        ]);
+        assert_eq!(&bytes[..14], &[b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0x01, 0xE0]);
+        assert_eq!(&bytes[22..], &[
+            0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+            0x00, 0x90, 60, 100,
+            0x83, 0x60, 0x80, 60, 0,
+            0x00, 0xFF, 0x2F, 0x00,
+        ]);
+        assert_eq!(u32::from_be_bytes(bytes[18..22].try_into().unwrap()) as usize, bytes.len() - 22);
+    }
+}
@@ -0,0 +1,110 @@
+// Forwards incoming messages to a MIDI output, so a local synth can be heard while the game gets keys
+
+use std::io;
+use std::sync::Mutex;
+
+use midir::{MidiOutput, MidiOutputConnection, MidiOutputPort};
+#[cfg(unix)]
+use midir::os::unix::VirtualOutput;
+
+use super::CONTROLLER;
+
+// Name of the virtual output as other software sees it
+#[cfg(unix)]
+pub const VIRTUAL_OUTPUT_NAME: &str = "miditoqwerty thru";
+
+const ALL_NOTES_OFF: u8 = 123;
+
+fn midi_error(err: impl std::fmt::Display) -> io::Error {
+    io::Error::other(format!("MIDI: {err}"))
+}
+
+#[derive(Clone)]
+pub enum ThruTarget {
+    Port(MidiOutputPort, String),
+    #[cfg(unix)]
+    Virtual, // our own output port, which other software connects to
+}
+
+impl ThruTarget {
+    pub fn name(&self) -> String {
+        match self {
+            ThruTarget::Port(_, name) => name.clone(),
+            #[cfg(unix)]
+            ThruTarget::Virtual => format!("{VIRTUAL_OUTPUT_NAME} (virtual)"),
+        }
+    }
+
+    // Every output that can be forwarded to, the virtual output last
+    pub fn list(midi: &MidiOutput) -> Vec<ThruTarget> {
+        #[allow(unused_mut)]
+        let mut targets: Vec<ThruTarget> = midi.ports().into_iter()
+            .filter_map(|port| midi.port_name(&port).ok().map(|name| ThruTarget::Port(port, name)))
+            .collect();
+
+        #[cfg(unix)]
+        {
+            // Forwarding to our own virtual output would only loop back to whoever reads it
+            targets.retain(|target| !target.name().contains(VIRTUAL_OUTPUT_NAME));
+            targets.push(ThruTarget::Virtual);
+        }
+        targets
+    }
+
+    // Finds an output by part of its name, "virtual" for the virtual output
+    pub fn find(midi: &MidiOutput, pattern: &str) -> Option<ThruTarget> {
+        let pattern = pattern.to_lowercase();
+        Self::list(midi).into_iter().find(|target| match target {
+            ThruTarget::Port(_, name) => name.to_lowercase().contains(&pattern),
+            #[cfg(unix)]
+            ThruTarget::Virtual => pattern == "virtual",
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct Thru {
+    connection: Mutex<Option<(String, MidiOutputConnection)>>,
+}
+
+impl Thru {
+    // Name of the output being forwarded to
+    pub fn target(&self) -> Option<String> {
+        self.connection.lock().unwrap().as_ref().map(|(name, _)| name.clone())
+    }
+
+    pub fn connect(&self, target: &ThruTarget) -> Result<(), io::Error> {
+        self.disconnect();
+
+        let midi = MidiOutput::new("miditoqwerty thru").map_err(midi_error)?;
+        let connection = match target {
+            ThruTarget::Port(port, name) => midi.connect(port, name).map_err(midi_error)?,
+            #[cfg(unix)]
+            ThruTarget::Virtual => midi.create_virtual(VIRTUAL_OUTPUT_NAME).map_err(midi_error)?,
+        };
+        *self.connection.lock().unwrap() = Some((target.name(), connection));
+        Ok(())
+    }
+
+    pub fn disconnect(&self) {
+        if let Some((name, connection)) = self.connection.lock().unwrap().take() {
+            log_info!(MidiIn, "Stopped forwarding to {}", name);
+            connection.close();
+        }
+    }
+
+    pub fn send(&self, message: &[u8]) {
+        if let Some((name, connection)) = self.connection.lock().unwrap().as_mut() {
+            if let Err(err) = connection.send(message) {
+                log_error!(MidiIn, "Failed to forward {:?} to {}: {}", message, name, err);
+            }
+        }
+    }
+
+    // Silences the synth, for when held notes won't get their note off (panic, transpose changes)
+    pub fn all_notes_off(&self) {
+        for channel in 0..16 {
+            self.send(&[CONTROLLER | channel, ALL_NOTES_OFF, 0]);
+        }
+    }
+}
@@ -0,0 +1,196 @@
+// Timing changes for played files: quantizing moves notes towards a grid to clean up a sloppy
+// recording, humanizing shifts them and their velocity at random so autoplay sounds less robotic.
+// Notes keep their length, other messages like pedals stay where they are.
+
+use super::{TimedMessage, NOTE_OFF, NOTE_ON};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timing {
+    pub bpm: f64, // tempo the grid is in
+    pub grid: u32, // note value notes are quantized to, 16 for sixteenths, 0 leaves them
+    pub strength: f64, // how far notes move towards the grid, from 0 to 1
+    pub jitter: u32, // largest random shift of a note, in milliseconds
+    pub velocity_jitter: u8, // largest random change of velocity
+}
+impl Default for Timing {
+    fn default() -> Self {
+        Timing { bpm: 120.0, grid: 0, strength: 1.0, jitter: 0, velocity_jitter: 0 }
+    }
+}
+
+impl Timing {
+    // Grids offered in the window, from quarters to thirty-seconds
+    pub const GRIDS: [u32; 4] = [4, 8, 16, 32];
+
+    pub fn changes_anything(&self) -> bool {
+        (self.grid > 0 && self.strength > 0.0) || self.jitter > 0 || self.velocity_jitter > 0
+    }
+}
+
+// Small and good enough for jitter, so no dependency is needed (splitmix64)
+struct Random(u64);
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Evenly spread from -max to max
+    fn spread(&mut self, max: i64) -> i64 {
+        match max {
+            0 => 0,
+            max => (self.next() % (2 * max as u64 + 1)) as i64 - max,
+        }
+    }
+}
+
+struct Note {
+    channel: u8,
+    note: u8,
+    velocity: u8,
+    start: u64,
+    end: Option<u64>, // None when the note is never released
+}
+
+// Applies `timing` to the notes of `messages`, which are in time order. `seed` picks the
+// random shifts, the same seed shifts the same way
+pub fn apply(messages: &[TimedMessage], timing: &Timing, seed: u64) -> Vec<TimedMessage> {
+    let mut notes: Vec<Note> = vec![];
+    let mut others: Vec<TimedMessage> = vec![];
+    for TimedMessage { time, message } in messages {
+        match *message.as_slice() {
+            [status, note, velocity] if status & 0xF0 == NOTE_ON && velocity > 0 => {
+                notes.push(Note { channel: status & 0x0F, note, velocity, start: *time, end: None });
+            }
+            [status, note, _] if matches!(status & 0xF0, NOTE_ON | NOTE_OFF) => {
+                let held = notes.iter_mut().find(|held| held.end.is_none() && (held.channel, held.note) == (status & 0x0F, note));
+                match held {
+                    Some(held) => held.end = Some(*time),
+                    None => others.push(TimedMessage { time: *time, message: message.clone() }),
+                }
+            }
+            _ => others.push(TimedMessage { time: *time, message: message.clone() }),
+        }
+    }
+
+    let grid = match timing.grid {
+        0 => 0.0,
+        grid => 60_000_000.0 / timing.bpm * 4.0 / grid as f64,
+    };
+    let mut random = Random(seed);
+    for note in &mut notes {
+        let mut shift = 0.0;
+        if grid > 0.0 {
+            let nearest = (note.start as f64 / grid).round() * grid;
+            shift += (nearest - note.start as f64) * timing.strength.clamp(0.0, 1.0);
+        }
+        shift += random.spread(timing.jitter as i64 * 1000) as f64;
+        let shift = shift.round() as i64;
+        note.start = note.start.saturating_add_signed(shift);
+        note.end = note.end.map(|end| end.saturating_add_signed(shift));
+
+        let velocity = note.velocity as i64 + random.spread(timing.velocity_jitter as i64);
+        note.velocity = velocity.clamp(1, 127) as u8;
+    }
+
+    // A note moved past the next one on its key would release it, so it ends where that one starts.
+    // Notes moved onto the start of the next one are left out, that key can only be pressed once
+    notes.sort_by_key(|note| (note.channel, note.note, note.start));
+    let mut merged = vec![false; notes.len()];
+    for index in 1..notes.len() {
+        let (previous, next) = (&notes[index - 1], &notes[index]);
+        if (previous.channel, previous.note) == (next.channel, next.note) {
+            let start = next.start;
+            let previous = &mut notes[index - 1];
+            previous.end = Some(previous.end.unwrap_or(start).min(start));
+            merged[index - 1] = previous.start >= start;
+        }
+    }
+    let notes = notes.into_iter().zip(merged).filter(|(_, merged)| !merged).map(|(note, _)| note);
+
+    // Releases first and presses last when they happen together, so repeated notes play again
+    let mut timed: Vec<(u64, u8, TimedMessage)> = others.into_iter().map(|message| (message.time, 1, message)).collect();
+    for note in notes {
+        timed.push((note.start, 2, TimedMessage { time: note.start, message: vec![NOTE_ON | note.channel, note.note, note.velocity] }));
+        if let Some(end) = note.end {
+            timed.push((end, 0, TimedMessage { time: end, message: vec![NOTE_OFF | note.channel, note.note, 0] }));
+        }
+    }
+    timed.sort_by_key(|&(time, order, _)| (time, order));
+    timed.into_iter().map(|(_, _, message)| message).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(start: u64, end: u64, note: u8, velocity: u8) -> [TimedMessage; 2] {
+        [
+            TimedMessage { time: start, message: vec![0x90, note, velocity] },
+            TimedMessage { time: end, message: vec![0x80, note, 0] },
+        ]
+    }
+
+    fn song(notes: &[[TimedMessage; 2]]) -> Vec<TimedMessage> {
+        let mut messages: Vec<TimedMessage> = notes.iter().flatten().cloned().collect();
+        messages.sort_by_key(|message| message.time);
+        messages
+    }
+
+    #[test]
+    fn quantizes_to_the_grid() {
+        // Sixteenths at 120 bpm are 125 ms apart
+        let sloppy = song(&[note(10_000, 100_000, 60, 90), note(260_000, 300_000, 62, 90)]);
+        let timing = Timing { grid: 16, ..Default::default() };
+        assert_eq!(apply(&sloppy, &timing, 0), song(&[note(0, 90_000, 60, 90), note(250_000, 290_000, 62, 90)]));
+
+        let halfway = Timing { strength: 0.5, ..timing };
+        assert_eq!(apply(&sloppy, &halfway, 0), song(&[note(5_000, 95_000, 60, 90), note(255_000, 295_000, 62, 90)]));
+
+        assert!(!Timing::default().changes_anything());
+        assert_eq!(apply(&sloppy, &Timing::default(), 7), sloppy);
+    }
+
+    #[test]
+    fn keeps_other_messages_and_repeated_notes() {
+        let mut messages = song(&[note(0, 130_000, 60, 90), note(120_000, 200_000, 60, 90)]);
+        messages.insert(0, TimedMessage { time: 0, message: vec![0xB0, 64, 127] });
+        let timing = Timing { grid: 4, ..Default::default() };
+
+        // The second note moves onto the first one, which is left out
+        let expected = vec![
+            TimedMessage { time: 0, message: vec![0xB0, 64, 127] },
+            TimedMessage { time: 0, message: vec![0x90, 60, 90] },
+            TimedMessage { time: 80_000, message: vec![0x80, 60, 0] },
+        ];
+        assert_eq!(apply(&messages, &timing, 0), expected);
+
+        // Only shortened when it would outlast the next one
+        let sixteenths = Timing { grid: 16, ..timing };
+        let mut expected = song(&[note(0, 125_000, 60, 90), note(125_000, 205_000, 60, 90)]);
+        expected.insert(0, TimedMessage { time: 0, message: vec![0xB0, 64, 127] });
+        assert_eq!(apply(&messages, &sixteenths, 0), expected);
+    }
+
+    #[test]
+    fn humanizes_within_bounds() {
+        let notes: Vec<[TimedMessage; 2]> = (0..50).map(|index| note(100_000 + index * 100_000, 150_000 + index * 100_000, 60 + (index % 12) as u8, 64)).collect();
+        let messages = song(&notes);
+        let timing = Timing { jitter: 20, velocity_jitter: 10, ..Default::default() };
+        let humanized = apply(&messages, &timing, 42);
+
+        assert_eq!(humanized.len(), messages.len());
+        assert_ne!(humanized, messages);
+        assert_eq!(apply(&messages, &timing, 42), humanized);
+        for (original, changed) in messages.iter().zip(&humanized) {
+            assert!(original.time.abs_diff(changed.time) <= 20_000);
+            assert_eq!(original.message[..2], changed.message[..2]);
+            if original.message[0] == 0x90 {
+                assert!(changed.message[2].abs_diff(64) <= 10);
+            }
+        }
+    }
+}
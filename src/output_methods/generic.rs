@@ -2,28 +2,31 @@ pub const REGULAR_VP_NOTES: &[u8] = "1!2@34$5%6^78*9(0qQwWeErtTyYuiIoOpPasSdDfgG
 
 use midi_event::Note;
 
-use super::InputMethod;
-use crate::keycodes::{ Key, KeyEvent, KeyEvents};
+use super::{key_for_char, InputMethod};
+use crate::keycodes::{Key, KeyEvent, KeyEvents, KeyName, Layout};
 
-fn str_for_note(note: Note) -> Option<String> {
-    let note_value = note as usize;
-
-    if note >= Note::C2 && note <= Note::C7 {
-        Some((*REGULAR_VP_NOTES.get(note as usize - Note::C2 as usize).unwrap() as char).to_string())
+pub fn char_for_note(note: u8) -> Option<char> {
+    if note >= Note::C2 as u8 && note <= Note::C7 as u8 {
+        REGULAR_VP_NOTES.get((note - Note::C2 as u8) as usize).map(|c| *c as char)
     } else {
         None
     }
 }
 
 pub struct Inner{
-    pressed_chars: [u8; 127], // OS key codes [idx] -> times pressed [u8]
+    notes: [Option<Key>; 128], // MIDI note -> key, resolved once in new()
+    pressed_chars: [u8; KeyName::COUNT], // KeyName [idx] -> times pressed [u8]
     space_down: bool,
     sostenuto_down: bool,
 }
 
 impl Inner {
-    pub fn new() -> Self {
-        Inner {pressed_chars: [0; 127], space_down: false, sostenuto_down: false}
+    pub fn new(layout: Layout) -> Self {
+        Inner {notes: Self::notes(layout), pressed_chars: [0; KeyName::COUNT], space_down: false, sostenuto_down: false}
+    }
+
+    fn notes(layout: Layout) -> [Option<Key>; 128] {
+        std::array::from_fn(|note| char_for_note(note as u8).and_then(|c| key_for_char("Generic", layout, c)))
     }
 }
 
@@ -34,23 +37,20 @@ impl InputMethod for Inner {
 
     fn press_note(&mut self, note: Note, velocity: u8) -> KeyEvents {
         let mut events: KeyEvents = Vec::new();
-        println!("[Generic]: Playing note {} ({:?}) at velocity {}", note as u32, note, velocity);
-
-        let key_string = str_for_note(note);
-
-        if key_string.is_none() {
-            println!("[Generic]: Impossible to press note {:?} with generic", note);
-            return vec![];
-        } let key_string = key_string.unwrap();
+        log_debug!(MidiIn, "[Generic]: Playing note {} ({:?}) at velocity {}", note as u32, note, velocity);
 
-        events.push(KeyEvent::Release(Key::new(&key_string))); // release in case it was already held
+        let keypress = match self.notes[note as usize] {
+            Some(keypress) => keypress,
+            None => {
+                log_debug!(MidiIn, "[Generic]: Impossible to press note {:?} with generic", note);
+                return vec![];
+            }
+        };
 
-        let keypress = Key::new(&key_string);
+        events.push(KeyEvent::Release(keypress)); // release in case it was already held
 
-        if keypress.shifted { events.push(KeyEvent::Press(Key::new("shift"))) };
-        events.push(KeyEvent::Press(keypress));
-        self.pressed_chars[keypress.code as usize] += 1;
-        if keypress.shifted { events.push(KeyEvent::Release(Key::new("shift"))) };
+        events.append(&mut keypress.press_with_modifiers());
+        self.pressed_chars[keypress.id as usize] += 1;
 
         //println!("{:?}", self.pressed_chars);
 
@@ -59,21 +59,19 @@ impl InputMethod for Inner {
 
     fn release_note(&mut self, note: Note) -> KeyEvents {
         let events: KeyEvents = vec![];
-        println!("[Generic]: Releasing note: {} ({:?})", note as u32, note);
+        log_debug!(MidiIn, "[Generic]: Releasing note: {} ({:?})", note as u32, note);
 
-        let key_string = str_for_note(note);
+        let keypress = match self.notes[note as usize] {
+            Some(keypress) => keypress,
+            None => {
+                log_debug!(MidiIn, "[Generic]: Impossible to press note {:?} with generic", note);
+                return vec![];
+            }
+        };
 
-        if key_string.is_none() {
-            println!("[Generic]: Impossible to press note {:?} with generic", note);
-            return vec![];
-        } let key_string = key_string.unwrap();
-
-        let keypress = Key::new(&key_string);
-        //println!("{:?}", keypress.code);
-
-        let presses = self.pressed_chars[keypress.code as usize];
+        let presses = self.pressed_chars[keypress.id as usize];
         if presses != 0 {
-            self.pressed_chars[keypress.code as usize] -= 1;
+            self.pressed_chars[keypress.id as usize] -= 1;
         }
 
         if presses > 1 {
@@ -86,36 +84,40 @@ impl InputMethod for Inner {
     }
 
     fn reset(&mut self, data: &str) {
-        self.pressed_chars = [0; 127];
+        self.pressed_chars = [0; KeyName::COUNT];
         self.space_down = false;
         self.sostenuto_down = false;
     }
 
     fn process_sustain(&mut self, value: u8) -> KeyEvents {
-        println!("[Generic]: Processing sustain: {}", value);
+        log_debug!(MidiIn, "[Generic]: Processing sustain: {}", value);
 
         if value >= 64 && !self.space_down {
             self.space_down = true;
-            vec![KeyEvent::Press(Key::new("space"))]
+            vec![KeyEvent::Press(KeyName::Space.into())]
         } else if value < 64 && self.space_down {
             self.space_down = false;
-            vec![KeyEvent::Release(Key::new("space"))]
+            vec![KeyEvent::Release(KeyName::Space.into())]
         } else {
             vec![]
         }
     }
 
     fn process_sostenuto(&mut self, value: u8) -> KeyEvents {
-        println!("[Generic]: Processing sostenuto: {}", value);
+        log_debug!(MidiIn, "[Generic]: Processing sostenuto: {}", value);
 
         if value >= 64 && !self.sostenuto_down {
             self.sostenuto_down = true;
-            vec![KeyEvent::Press(Key::new("rightbrace"))]
+            vec![KeyEvent::Press(KeyName::RightBrace.into())]
         } else if value < 64 && self.sostenuto_down {
             self.sostenuto_down = false;
-            vec![KeyEvent::Release(Key::new("rightbrace"))]
+            vec![KeyEvent::Release(KeyName::RightBrace.into())]
         } else {
             vec![]
         }
     }
-}
\ No newline at end of file
+
+    fn set_layout(&mut self, layout: Layout) {
+        self.notes = Self::notes(layout);
+    }
+}
@@ -0,0 +1,184 @@
+// Feeds scripted MIDI through an InputMethod and compares the resulting key events against golden files.
+// Run the tests with UPDATE_GOLDEN=1 to rewrite the golden files after an intended change.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+
+use midi_event::Parse;
+
+use super::{events_for_midi, InputMethod};
+use crate::keyboard_provider::{KeyboardBackend, RecordingKeyboard};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Step {
+    On(u8, u8),
+    Off(u8),
+    Sustain(u8),
+    Sostenuto(u8),
+    Reset(&'static str),
+}
+impl Step {
+    fn message(&self) -> Option<[u8; 3]> {
+        match *self {
+            Step::On(note, velocity) => Some([0x90, note, velocity]),
+            Step::Off(note) => Some([0x80, note, 0]),
+            Step::Sustain(value) => Some([0xB0, 64, value]),
+            Step::Sostenuto(value) => Some([0xB0, 66, value]),
+            Step::Reset(_) => None,
+        }
+    }
+}
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Step::On(note, velocity) => write!(f, "on {note} {velocity}"),
+            Step::Off(note) => write!(f, "off {note}"),
+            Step::Sustain(value) => write!(f, "sustain {value}"),
+            Step::Sostenuto(value) => write!(f, "sostenuto {value}"),
+            Step::Reset(data) => write!(f, "reset {data}"),
+        }
+    }
+}
+
+pub const CHORD: &[Step] = &[
+    Step::On(60, 100), Step::On(64, 100), Step::On(67, 100),
+    Step::Off(60), Step::Off(64), Step::Off(67),
+];
+
+// Includes keys shared between a note and its sharp, and a NoteOn with velocity 0
+pub const REPEATED_NOTES: &[Step] = &[
+    Step::On(60, 90), Step::On(60, 90), Step::Off(60), Step::Off(60),
+    Step::On(61, 90), Step::On(60, 90), Step::Off(61), Step::Off(60),
+    Step::On(62, 90), Step::On(62, 0),
+];
+
+pub const PEDALS: &[Step] = &[
+    Step::Sustain(127), Step::On(48, 80), Step::Off(48),
+    Step::Sustain(127), Step::Sustain(0), Step::Sustain(0),
+    Step::Sostenuto(100), Step::On(50, 80), Step::Sostenuto(0), Step::Off(50),
+];
+
+// Edges of the regular and 88-key ranges, and notes no method can play
+pub const OUT_OF_RANGE: &[Step] = &[
+    Step::On(0, 100), Step::Off(0),
+    Step::On(20, 100), Step::Off(20),
+    Step::On(21, 100), Step::Off(21),
+    Step::On(35, 100), Step::Off(35),
+    Step::On(36, 100), Step::Off(36),
+    Step::On(96, 100), Step::Off(96),
+    Step::On(97, 100), Step::Off(97),
+    Step::On(108, 100), Step::Off(108),
+    Step::On(109, 100), Step::Off(109),
+    Step::On(127, 100), Step::Off(127),
+];
+
+pub const VELOCITY_SWEEP: &[Step] = &[
+    Step::On(60, 1), Step::Off(60),
+    Step::On(60, 32), Step::Off(60),
+    Step::On(60, 64), Step::Off(60),
+    Step::On(60, 100), Step::Off(60),
+    Step::On(60, 127), Step::Off(60),
+    Step::Reset("velocity-off"), Step::On(60, 127), Step::Off(60),
+];
+
+pub fn run_script(method: &mut dyn InputMethod, steps: &[Step]) -> String {
+    let mut keyboard = RecordingKeyboard::new();
+    let mut output = String::new();
+
+    for step in steps {
+        writeln!(output, "> {step}").unwrap();
+
+        match step.message() {
+            Some(message) => match midi_event::Event::parse(&message) {
+                Some(midi_event::Event::Midi(event)) => keyboard.write_many(events_for_midi(method, event.event)),
+                _ => panic!("Scripted message {:?} did not parse as a MIDI event", message),
+            },
+            None => if let Step::Reset(data) = step { method.reset(data) },
+        }
+
+        for recorded in keyboard.take() {
+            writeln!(output, "    {}", recorded.event).unwrap();
+        }
+    }
+
+    output
+}
+
+pub fn assert_golden(method: &mut dyn InputMethod, golden: &str, steps: &[Step]) {
+    let actual = run_script(method, steps);
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/output_methods/golden").join(format!("{golden}.txt"));
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, &actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("Missing golden file {}: {err}", path.display()))
+        .replace("\r\n", "\n");
+    assert_eq!(expected, actual, "Key events differ from {}, rerun with UPDATE_GOLDEN=1 if this is intended", path.display());
+}
+
+mod tests {
+    use super::*;
+    use crate::keycodes::Layout;
+    use crate::output_methods::{generic, piano_rooms, pv};
+
+    const SCRIPTS: &[(&str, &[Step])] = &[
+        ("chord", CHORD),
+        ("repeated_notes", REPEATED_NOTES),
+        ("pedals", PEDALS),
+        ("out_of_range", OUT_OF_RANGE),
+        ("velocity_sweep", VELOCITY_SWEEP),
+    ];
+
+    type NewMethod = fn() -> Box<dyn InputMethod>;
+
+    // Golden directory and a fresh method for every script
+    fn methods() -> [(&'static str, NewMethod); 3] {
+        [
+            ("generic", || Box::new(generic::Inner::new(Layout::Us))),
+            ("pv", || Box::new(pv::Inner::new(Layout::Us))),
+            ("piano_rooms", || Box::new(piano_rooms::Inner)),
+        ]
+    }
+
+    // Key events written for the first `step` of the script
+    fn events_after<'a>(output: &'a str, step: &str) -> Vec<&'a str> {
+        output.lines()
+            .skip_while(|line| *line != format!("> {step}"))
+            .skip(1)
+            .take_while(|line| !line.starts_with('>'))
+            .map(str::trim)
+            .collect()
+    }
+
+    #[test]
+    fn golden_files() {
+        for (name, method) in methods() {
+            for (case, steps) in SCRIPTS {
+                assert_golden(method().as_mut(), &format!("{name}/{case}"), steps);
+            }
+        }
+    }
+
+    #[test]
+    fn piano_rooms_ignores_sostenuto() {
+        let output = run_script(&mut piano_rooms::Inner, PEDALS);
+        assert!(events_after(&output, "sostenuto 100").is_empty());
+        assert!(events_after(&output, "sostenuto 0").is_empty());
+    }
+
+    #[test]
+    fn only_piano_rooms_plays_every_note() {
+        for (name, method) in methods() {
+            let output = run_script(method().as_mut(), OUT_OF_RANGE);
+            for note in ["on 0 100", "on 127 100"] {
+                let pressed = events_after(&output, note).iter().any(|event| event.starts_with("press"));
+                assert_eq!(pressed, name == "piano_rooms", "{name} for {note}");
+            }
+        }
+    }
+}
@@ -1,13 +1,21 @@
 #![allow(unused_variables)]
 #![allow(dead_code)]
 
-use midi_event::Note;
+use midi_event::{MidiEventType, Note};
 
-use crate::keycodes::{KeyEvents, Key};
+use crate::keycodes::{Key, KeyEvents, KeyName, Layout};
 
 mod generic;
 mod pv;
 mod piano_rooms;
+#[cfg(test)]
+mod harness;
+
+#[cfg(test)]
+pub use generic::REGULAR_VP_NOTES;
+// Characters typed for each note, for writing and reading sheets
+pub use generic::char_for_note as generic_char_for_note;
+pub use pv::char_for_note as pv_char_for_note;
 
 pub trait InputMethod {
     fn get_name(&self) -> String;
@@ -16,11 +24,101 @@ pub trait InputMethod {
     fn reset(&mut self, data: &str);
     fn process_sustain(&mut self, value: u8) -> KeyEvents;
     fn process_sostenuto(&mut self, value: u8) -> KeyEvents;
+    // Methods that type characters look their keys up again for the new layout
+    fn set_layout(&mut self, layout: Layout) {}
+}
+
+// Looks up the key typing `c`, warning when the layout can't type it well
+fn key_for_char(method: &str, layout: Layout, c: char) -> Option<Key> {
+    let key = layout.key_for_char(c);
+    match key {
+        None => log_warn!(App, "[{}]: {} has no key for {:?}", method, layout.name(), c),
+        Some(key) if layout.is_dead_key(key) => log_warn!(App, "[{}]: {:?} is a dead key on {}, it only types together with the next key", method, c, layout.name()),
+        Some(_) => {}
+    }
+    key
+}
+
+// Every key the output methods can press on any layout, with the modifiers they hold for it,
+// so the uinput device doesn't have to pretend to be a full keyboard
+pub fn possible_keys() -> Vec<KeyName> {
+    let mut keys = vec![KeyName::Shift, KeyName::RightAlt, KeyName::LeftAlt, KeyName::LeftCtrl, KeyName::Space, KeyName::RightBrace, KeyName::KpAsterisk];
+    keys.extend(piano_rooms::PIANO_ROOMS_KEYS);
+
+    // PV types every note Generic does. Key::parse is the US fallback of the velocity keys
+    let chars = (0..128).filter_map(pv::char_for_note).chain(pv::VELOCITY_KEYS.iter().map(|c| *c as char));
+    for c in chars {
+        keys.extend(Key::parse(&c.to_string()).map(|key| key.id));
+        keys.extend(Layout::ALL.iter().filter_map(|layout| layout.key_for_char(c)).map(|key| key.id));
+    }
+
+    let mut unique = vec![];
+    for key in keys {
+        if !unique.contains(&key) {
+            unique.push(key);
+        }
+    }
+    unique
 }
 
+// Turns one parsed MIDI event into the key events the method wants sent
+pub fn events_for_midi(method: &mut dyn InputMethod, event: MidiEventType) -> KeyEvents {
+    match event {
+        MidiEventType::NoteOn(note, velocity) => {
+            if velocity == 0 { // Some pianos (Alesis Recital Grand, reportedly) send a NoteOn with 0 velocity instead of NoteOff
+                method.release_note(note)
+            } else { // Non-zero, real down press
+                method.press_note(note, velocity)
+            }
+        }
+        MidiEventType::NoteOff(note, _) => {
+            method.release_note(note)
+        }
+        MidiEventType::Controller(control, value) => {
+            match control {
+                64 => { method.process_sustain(value) }
+                66 => { method.process_sostenuto(value) }
+                other_control => { log_debug!(MidiIn, "Unknown control to set: {other_control}"); vec![] }
+            }
+        }
+        anything_else => {
+            log_debug!(MidiIn, "Unsupported MIDI event type: {:?}", anything_else);
+            vec![]
+        }
+    }
+}
 
 pub mod unified {
     pub use super::generic::Inner as generic_inner;
     pub use super::pv::Inner as pv_inner;
     pub use super::piano_rooms::Inner as piano_rooms_inner;
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use midi_event::Parse;
+
+    use super::*;
+
+    #[test]
+    fn possible_keys_cover_every_method() {
+        let possible = possible_keys();
+        assert!(!possible.contains(&KeyName::Esc) && !possible.contains(&KeyName::F1));
+
+        for layout in Layout::ALL {
+            let methods: [Box<dyn InputMethod>; 3] = [Box::new(generic::Inner::new(layout)), Box::new(pv::Inner::new(layout)), Box::new(piano_rooms::Inner)];
+            for mut method in methods {
+                let mut messages: Vec<[u8; 3]> = (0..128).flat_map(|note| [[0x90, note, 100], [0x80, note, 0]]).collect();
+                messages.extend((1..128).map(|velocity| [0x90, 60, velocity]));
+                messages.extend([[0xB0, 64, 127], [0xB0, 64, 0], [0xB0, 66, 127], [0xB0, 66, 0]]);
+
+                for message in messages {
+                    let Some(midi_event::Event::Midi(event)) = midi_event::Event::parse(&message) else { panic!("{message:?} isn't a MIDI event") };
+                    for key_event in events_for_midi(method.as_mut(), event.event) {
+                        let id = key_event.key().id;
+                        assert!(possible.contains(&id), "{} sends {:?} on {}", method.get_name(), id, layout.name());
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,10 +1,10 @@
 use midi_event::Note;
 
-use super::Key;
-use crate::{output_methods::InputMethod, keycodes::{KeyEvent, KeyEvents}};
+use crate::{output_methods::InputMethod, keycodes::{KeyEvent, KeyEvents, KeyName}};
 
-const PIANO_ROOMS_KEYS: [&str; 12] = [
-    "kp0", "kp1", "kp2", "kp3", "kp4", "kp5", "kp6", "kp7", "kp8", "kp9", "kpminus", "kpplus"
+pub(super) const PIANO_ROOMS_KEYS: [KeyName; 12] = [
+    KeyName::Kp0, KeyName::Kp1, KeyName::Kp2, KeyName::Kp3, KeyName::Kp4, KeyName::Kp5,
+    KeyName::Kp6, KeyName::Kp7, KeyName::Kp8, KeyName::Kp9, KeyName::KpMinus, KeyName::KpPlus
 ];
 
 pub struct Inner;
@@ -15,7 +15,7 @@ impl InputMethod for Inner {
     }
 
     fn press_note(&mut self, note: Note, velocity: u8) -> KeyEvents {
-        println!("[PianoRooms]: Playing note {:?} at velocity {}", note, velocity);
+        log_debug!(MidiIn, "[PianoRooms]: Playing note {:?} at velocity {}", note, velocity);
 
         // original code:
         //      Array = ['num0', 'numpad1', 'numpad2', 'numpad3', 'numpad4', 'numpad5', 'numpad6', 'numpad7', 'numpad8', 'numpad9', 'subtract', 'add']
@@ -23,33 +23,33 @@ impl InputMethod for Inner {
         //      ToSend = [math.floor(msg.note/12),math.floor(msg.note%12),math.floor(msg.velocity/12),math.floor(msg.velocity%12)]
 
         let mut events: KeyEvents = vec![
-            KeyEvent::Press(Key::new("kpasterisk")),
-            KeyEvent::Release(Key::new("kpasterisk"))
+            KeyEvent::Press(KeyName::KpAsterisk.into()),
+            KeyEvent::Release(KeyName::KpAsterisk.into())
         ];
 
         let to_send: Vec<u8> = vec![note as u8 / 12, note as u8 % 12, velocity / 12, velocity % 12];
         to_send.iter().for_each(|num| {
-            let key = PIANO_ROOMS_KEYS.get(*num as usize).expect("Invalid Piano Rooms key");
-            events.push(KeyEvent::Press(Key::new(key)));
-            events.push(KeyEvent::Release(Key::new(key)));
+            let key = *PIANO_ROOMS_KEYS.get(*num as usize).expect("Invalid Piano Rooms key");
+            events.push(KeyEvent::Press(key.into()));
+            events.push(KeyEvent::Release(key.into()));
         });
 
         events
     }
 
     fn release_note(&mut self, note: Note) -> KeyEvents {
-        println!("[PianoRooms]: Releasing note: {:?}", note);
+        log_debug!(MidiIn, "[PianoRooms]: Releasing note: {:?}", note);
 
         let mut events: KeyEvents = vec![
-            KeyEvent::Press(Key::new("kpasterisk")),
-            KeyEvent::Release(Key::new("kpasterisk"))
+            KeyEvent::Press(KeyName::KpAsterisk.into()),
+            KeyEvent::Release(KeyName::KpAsterisk.into())
         ];
 
         let to_send: Vec<u8> = vec![note as u8 / 12, note as u8 % 12, 0, 0];
         to_send.iter().for_each(|num| {
-            let key = PIANO_ROOMS_KEYS.get(*num as usize).expect("Invalid Piano Rooms key");
-            events.push(KeyEvent::Press(Key::new(key)));
-            events.push(KeyEvent::Release(Key::new(key)));
+            let key = *PIANO_ROOMS_KEYS.get(*num as usize).expect("Invalid Piano Rooms key");
+            events.push(KeyEvent::Press(key.into()));
+            events.push(KeyEvent::Release(key.into()));
         });
 
         events
@@ -58,27 +58,27 @@ impl InputMethod for Inner {
     fn reset(&mut self, data: &str) {}
 
     fn process_sustain(&mut self, value: u8) -> KeyEvents {
-        println!("[PianoRooms]: Processing sustain: {}", value);
+        log_debug!(MidiIn, "[PianoRooms]: Processing sustain: {}", value);
 
         let mut events: KeyEvents = vec![
-            KeyEvent::Press(Key::new("kpasterisk")),
-            KeyEvent::Release(Key::new("kpasterisk"))
+            KeyEvent::Press(KeyName::KpAsterisk.into()),
+            KeyEvent::Release(KeyName::KpAsterisk.into())
         ];
 
         let control = 143;
         let to_send: Vec<u8> = vec![control / 12, control % 12, value / 12, value % 12];
         to_send.iter().for_each(|num| {
-            let key = PIANO_ROOMS_KEYS.get(*num as usize).expect("Invalid Piano Rooms key");
-            events.push(KeyEvent::Press(Key::new(key)));
-            events.push(KeyEvent::Release(Key::new(key)));
+            let key = *PIANO_ROOMS_KEYS.get(*num as usize).expect("Invalid Piano Rooms key");
+            events.push(KeyEvent::Press(key.into()));
+            events.push(KeyEvent::Release(key.into()));
         });
 
         events
     }
 
     fn process_sostenuto(&mut self, value: u8) -> KeyEvents {
-        println!("[PianoRooms]: Processing sostenuto: {}", value);
+        log_debug!(MidiIn, "[PianoRooms]: Processing sostenuto: {}", value);
         // Piano Rooms uses a custom protocol; sostenuto not in original spec
         vec![]
     }
-}
\ No newline at end of file
+}
@@ -4,12 +4,13 @@ pub const HIGH_VP_NOTES: &[u8] = "yuiopasdfghj".as_bytes();
 
 use midi_event::Note;
 
-use crate::keycodes::KeyEvent;
+use crate::keycodes::{KeyEvent, KeyName, Layout};
 
-use super::{KeyEvents, InputMethod, Key};
+use super::{key_for_char, KeyEvents, InputMethod, Key};
 
-pub fn string_for_velocity(velocity: u8) -> String {
-    const VELOCITY_KEYS: &[u8] = "1234567890qwertyuiopasdfghjklzxc".as_bytes();
+pub(super) const VELOCITY_KEYS: &[u8] = "1234567890qwertyuiopasdfghjklzxc".as_bytes();
+
+fn index_for_velocity(velocity: u8) -> usize {
     const VELOCITY_LIST: [u8; 32] = [
         4, 8, 12, 16,
         20, 24, 28, 32,
@@ -21,59 +22,76 @@ pub fn string_for_velocity(velocity: u8) -> String {
         116, 120, 124, 127,
     ];
 
-    let (index, closest) = VELOCITY_LIST
+    VELOCITY_LIST
         .iter()
         .enumerate()
         .min_by_key(|&(_, &v)| (velocity as i32 - v as i32).abs())
-        .unwrap_or((0, &VELOCITY_LIST[15])); // Default to index 15
-
-    // println!("velocity: {}", velocity);
-    // println!("index: {}", index);
-    (*VELOCITY_KEYS.get(index).expect("Velocity index issue") as char).to_string()
+        .map(|(index, _)| index)
+        .unwrap_or(15) // Default to index 15
 }
 
-pub fn events_for_velocity(velocity: u8) -> KeyEvents {
-    let mut events = vec![];
-
-    events.push(KeyEvent::Press(Key::new("leftalt")));
-
-    let velocity_keypress = string_for_velocity(velocity);
-
-    // release all previous sames
-    events.push(KeyEvent::Release(Key::new(&velocity_keypress)));
-
-    events.push(KeyEvent::Press(Key::new(&velocity_keypress)));
-    events.push(KeyEvent::Release(Key::new(&velocity_keypress)));
-
-    events.push(KeyEvent::Release(Key::new("leftalt")));
-
-    events
-}
-
-fn str_for_note(note: Note) -> Option<String> {
-    let note_value = note as usize;
-
-    if note < Note::C2 {
-        Some((*LOW_VP_NOTES.get((note as i8 - Note::B1 as i8).abs() as usize).unwrap() as char).to_string())
+pub fn char_for_note(note: u8) -> Option<char> {
+    // Outside of the 88 keys there's nothing to press
+    if note < Note::C2 as u8 {
+        LOW_VP_NOTES.get((note as i8 - Note::B1 as i8).unsigned_abs() as usize).map(|c| *c as char)
     }
-    else if note > Note::C7 {
-        Some((*HIGH_VP_NOTES.get((note as i8 - Note::Cs7 as i8).abs() as usize).unwrap() as char).to_string())
+    else if note > Note::C7 as u8 {
+        HIGH_VP_NOTES.get((note as i8 - Note::Cs7 as i8).unsigned_abs() as usize).map(|c| *c as char)
     }
     else {
-        Some((*REGULAR_VP_NOTES.get(note as usize - Note::C2 as usize).unwrap() as char).to_string())
+        REGULAR_VP_NOTES.get((note - Note::C2 as u8) as usize).map(|c| *c as char)
     }
 }
 
 pub struct Inner {
-    pressed_chars: [u8; 127], // OS key codes [idx] -> times pressed [u8]
+    notes: [Option<Key>; 128], // MIDI note -> key, resolved once in new()
+    velocity_keys: [Key; 32],
+    pressed_chars: [u8; KeyName::COUNT], // KeyName [idx] -> times pressed [u8]
     space_down: bool,
     sostenuto_down: bool,
     velocity: bool
 }
 
 impl Inner {
-    pub fn new() -> Self {
-        Inner {pressed_chars: [0; 127], space_down: false, sostenuto_down: false, velocity: true}
+    pub fn new(layout: Layout) -> Self {
+        Inner {
+            notes: Self::notes(layout),
+            velocity_keys: Self::velocity_keys(layout),
+            pressed_chars: [0; KeyName::COUNT],
+            space_down: false,
+            sostenuto_down: false,
+            velocity: true
+        }
+    }
+
+    fn notes(layout: Layout) -> [Option<Key>; 128] {
+        std::array::from_fn(|note| char_for_note(note as u8).and_then(|c| key_for_char("PV", layout, c)))
+    }
+
+    // Every layout has these, falling back to the US position just in case
+    fn velocity_keys(layout: Layout) -> [Key; 32] {
+        std::array::from_fn(|index| {
+            let c = VELOCITY_KEYS[index] as char;
+            key_for_char("PV", layout, c).unwrap_or_else(|| Key::parse(&c.to_string()).expect("Unknown velocity key"))
+        })
+    }
+
+    fn events_for_velocity(&self, velocity: u8) -> KeyEvents {
+        let mut events = vec![];
+
+        events.push(KeyEvent::Press(KeyName::LeftAlt.into()));
+
+        let velocity_keypress = self.velocity_keys[index_for_velocity(velocity)];
+
+        // release all previous sames
+        events.push(KeyEvent::Release(velocity_keypress));
+
+        events.append(&mut velocity_keypress.press_with_modifiers());
+        events.push(KeyEvent::Release(velocity_keypress));
+
+        events.push(KeyEvent::Release(KeyName::LeftAlt.into()));
+
+        events
     }
 }
 
@@ -85,47 +103,49 @@ impl InputMethod for Inner {
 
     fn press_note(&mut self, note: Note, velocity: u8) -> KeyEvents {
         let mut events: KeyEvents = Vec::new();
-        println!("[PV]: Playing note {} ({:?}) at velocity {}", note as u32, note, velocity);
+        log_debug!(MidiIn, "[PV]: Playing note {} ({:?}) at velocity {}", note as u32, note, velocity);
+
+        let keypress = match self.notes[note as usize] {
+            Some(keypress) => keypress,
+            None => {
+                log_debug!(MidiIn, "[PV]: Impossible to press note {:?} with PV", note);
+                return vec![];
+            }
+        };
 
         if self.velocity {
-            events.append(&mut events_for_velocity(velocity));
+            events.append(&mut self.events_for_velocity(velocity));
         }
 
         let is_88_key = note < Note::C2 || note > Note::C7;
-        if is_88_key { events.push(KeyEvent::Press(Key::new("leftctrl"))) };
-
-        let keystring = str_for_note(note).unwrap();
-        let keypress = Key::new(&keystring); // meta / for info
+        if is_88_key { events.push(KeyEvent::Press(KeyName::LeftCtrl.into())) };
 
         // Release just to make sure that we can actually play it again
-        events.push(KeyEvent::Release(Key::new(&keystring)));
+        events.push(KeyEvent::Release(keypress));
 
-        if keypress.shifted { events.push(KeyEvent::Press(Key::new("shift"))) };
-        events.push(KeyEvent::Press(Key::new(&keystring)));
-        self.pressed_chars[keypress.code as usize] += 1;
-        if keypress.shifted { events.push(KeyEvent::Release(Key::new("shift"))) };
+        events.append(&mut keypress.press_with_modifiers());
+        self.pressed_chars[keypress.id as usize] += 1;
 
-        if is_88_key { events.push(KeyEvent::Release(Key::new("leftctrl"))) };
+        if is_88_key { events.push(KeyEvent::Release(KeyName::LeftCtrl.into())) };
 
         events
     }
 
     fn release_note(&mut self, note: Note) -> KeyEvents {
         let events: KeyEvents = vec![];
-        println!("[PV]: Releasing note: {} ({:?})", note as u32, note);
-
-        let key_string = str_for_note(note);
-
-        if key_string.is_none() {
-            println!("[Generic]: Impossible to press note {:?} with PV", note);
-            return vec![];
-        } let key_string = key_string.unwrap();
+        log_debug!(MidiIn, "[PV]: Releasing note: {} ({:?})", note as u32, note);
 
-        let keypress = Key::new(&key_string);
+        let keypress = match self.notes[note as usize] {
+            Some(keypress) => keypress,
+            None => {
+                log_debug!(MidiIn, "[PV]: Impossible to press note {:?} with PV", note);
+                return vec![];
+            }
+        };
 
-        let presses = self.pressed_chars[keypress.code as usize];
+        let presses = self.pressed_chars[keypress.id as usize];
         if presses != 0 {
-            self.pressed_chars[keypress.code as usize] -= 1;
+            self.pressed_chars[keypress.id as usize] -= 1;
         }
 
         if presses > 1 {
@@ -139,37 +159,42 @@ impl InputMethod for Inner {
     }
 
     fn reset(&mut self, data: &str) {
-        self.pressed_chars = [0; 127];
+        self.pressed_chars = [0; KeyName::COUNT];
         self.space_down = false;
         self.sostenuto_down = false;
         self.velocity = !data.starts_with("velocity-off");
     }
 
     fn process_sustain(&mut self, value: u8) -> KeyEvents {
-        println!("[PV]: Processing sustain: {}", value);
+        log_debug!(MidiIn, "[PV]: Processing sustain: {}", value);
 
         if value >= 64 && !self.space_down {
             self.space_down = true;
-            vec![KeyEvent::Press(Key::new("space"))]
+            vec![KeyEvent::Press(KeyName::Space.into())]
         } else if value < 64 && self.space_down {
             self.space_down = false;
-            vec![KeyEvent::Release(Key::new("space"))]
+            vec![KeyEvent::Release(KeyName::Space.into())]
         } else {
             vec![]
         }
     }
 
     fn process_sostenuto(&mut self, value: u8) -> KeyEvents {
-        println!("[PV]: Processing sostenuto: {}", value);
+        log_debug!(MidiIn, "[PV]: Processing sostenuto: {}", value);
 
         if value >= 64 && !self.sostenuto_down {
             self.sostenuto_down = true;
-            vec![KeyEvent::Press(Key::new("rightbrace"))]
+            vec![KeyEvent::Press(KeyName::RightBrace.into())]
         } else if value < 64 && self.sostenuto_down {
             self.sostenuto_down = false;
-            vec![KeyEvent::Release(Key::new("rightbrace"))]
+            vec![KeyEvent::Release(KeyName::RightBrace.into())]
         } else {
             vec![]
         }
     }
-}
\ No newline at end of file
+
+    fn set_layout(&mut self, layout: Layout) {
+        self.notes = Self::notes(layout);
+        self.velocity_keys = Self::velocity_keys(layout);
+    }
+}
@@ -0,0 +1,100 @@
+// Profiles are text files of settings to switch between songs or games in one go, with one
+// `name = value` per line and # comments. Settings left out keep their current value.
+//
+//   method = pv
+//   layout = de
+//   transpose = -12
+//   velocity = off
+//   output = on
+//   program-change = off
+//   target-window = process:RobloxPlayerBeta.exe
+
+use std::fs;
+use std::path::Path;
+
+use crate::focus::WindowFilter;
+use crate::keycodes::Layout;
+use crate::midi::MAX_TRANSPOSE;
+use crate::AvailableInputMethod;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Profile {
+    pub method: Option<AvailableInputMethod>,
+    pub layout: Option<Layout>,
+    pub transpose: Option<i8>,
+    pub velocity: Option<bool>,
+    pub output: Option<bool>,
+    pub programs_select_methods: Option<bool>,
+    pub target_window: Option<Option<WindowFilter>>, // Some(None) targets any window
+}
+
+fn parse_switch(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "on" | "true" | "yes" => Some(true),
+        "off" | "false" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+impl Profile {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut profile = Profile::default();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("Line {}: {message}", number + 1);
+            let (name, value) = line.split_once('=').ok_or_else(|| error(format!("Expected <setting> = <value>, got {line:?}")))?;
+            let value = value.trim();
+            let invalid = || error(format!("Invalid {}: {value:?}", name.trim()));
+
+            match name.trim().to_ascii_lowercase().as_str() {
+                "method" => profile.method = Some(AvailableInputMethod::parse(value).ok_or_else(invalid)?),
+                "layout" => profile.layout = Some(Layout::parse(value).ok_or_else(invalid)?),
+                "transpose" => {
+                    let transpose = value.trim_start_matches('+').parse::<i8>().ok().filter(|transpose| transpose.abs() <= MAX_TRANSPOSE);
+                    profile.transpose = Some(transpose.ok_or_else(invalid)?);
+                }
+                "velocity" => profile.velocity = Some(parse_switch(value).ok_or_else(invalid)?),
+                "output" => profile.output = Some(parse_switch(value).ok_or_else(invalid)?),
+                "program-change" => profile.programs_select_methods = Some(parse_switch(value).ok_or_else(invalid)?),
+                "target-window" => profile.target_window = Some(match value {
+                    "" => None,
+                    pattern => Some(WindowFilter::parse(pattern).map_err(error)?),
+                }),
+                other => return Err(error(format!("Unknown setting {other:?}"))),
+            }
+        }
+        Ok(profile)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("Unable to read profile {}: {err}", path.display()))?;
+        Self::parse(&text).map_err(|err| format!("{}: {err}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_profiles() {
+        let profile = Profile::parse("# For Roblox\nmethod = Piano Visualizations\nlayout = de\ntranspose = +12 # an octave up\n\nvelocity = off\ntarget-window =\n").unwrap();
+        assert_eq!(profile, Profile {
+            method: Some(AvailableInputMethod::PV),
+            layout: Some(Layout::De),
+            transpose: Some(12),
+            velocity: Some(false),
+            target_window: Some(None),
+            ..Default::default()
+        });
+
+        assert_eq!(Profile::parse("").unwrap(), Profile::default());
+        assert!(Profile::parse("transpose = 100").unwrap_err().starts_with("Line 1: Invalid transpose"));
+        assert!(Profile::parse("\ncolour = blue").unwrap_err().starts_with("Line 2: Unknown setting"));
+        assert!(Profile::parse("method pv").is_err());
+    }
+}
@@ -0,0 +1,418 @@
+// A local HTTP and WebSocket endpoint for controlling the app from a Stream Deck, a browser or a
+// script without touching the window. There's no authentication, so it should only listen on
+// localhost or a trusted network. Browsers may only use it from the pages it serves itself, so
+// websites open elsewhere can't send it commands.
+//
+//   GET  /status    the current Status as JSON
+//   POST /command   runs the command in the body, e.g. "toggle-output" or "play song.mid"
+//   GET  /ws        WebSocket pushing Status whenever it changes and taking commands as text messages
+//   GET  /overlay   page showing played notes and typed keys, for a browser source in OBS
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::latency::Summary;
+
+mod websocket;
+
+const MAX_BODY: usize = 64 * 1024;
+// Often enough for the overlay to follow quick notes
+const STATUS_INTERVAL: Duration = Duration::from_millis(30);
+
+const OVERLAY: &str = include_str!("overlay.html");
+
+const USAGE: &str = "\
+miditoqwerty remote control
+
+GET  /status    current state as JSON
+POST /command   run the command in the body
+GET  /ws        WebSocket: status pushed as it changes, commands sent as text messages
+GET  /overlay   played notes and typed keys, for a browser source in OBS
+";
+
+#[derive(Debug, Clone, Default)]
+pub struct Status {
+    pub port: String,
+    pub method: String,
+    pub layout: String,
+    pub output: bool,
+    pub output_allowed: bool, // output is on and the target window, if any, has focus
+    pub transpose: i8,
+    pub held_notes: Vec<u8>,
+    pub held_keys: Vec<String>, // what the keys held on the output type
+    pub sustain: bool,
+    pub sostenuto: bool,
+    pub latency: Summary,
+    pub recording: bool,
+    pub playing: Option<String>,
+}
+
+pub fn json_string(s: &str) -> String {
+    let mut json = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+impl Status {
+    pub fn to_json(&self) -> String {
+        let milliseconds = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        let held_notes: Vec<String> = self.held_notes.iter().map(|note| note.to_string()).collect();
+        let held_keys: Vec<String> = self.held_keys.iter().map(|key| json_string(key)).collect();
+        format!(
+            concat!(
+                "{{\"port\":{},\"method\":{},\"layout\":{},\"output\":{},\"output_allowed\":{},\"transpose\":{},",
+                "\"held_notes\":[{}],\"held_keys\":[{}],\"sustain\":{},\"sostenuto\":{},",
+                "\"latency\":{{\"samples\":{},\"min_ms\":{:.3},\"avg_ms\":{:.3},\"p99_ms\":{:.3},\"events_per_second\":{:.1}}},",
+                "\"recording\":{},\"playing\":{}}}",
+            ),
+            json_string(&self.port), json_string(&self.method), json_string(&self.layout), self.output, self.output_allowed, self.transpose,
+            held_notes.join(","), held_keys.join(","), self.sustain, self.sostenuto,
+            self.latency.count, milliseconds(self.latency.min), milliseconds(self.latency.avg), milliseconds(self.latency.p99), self.latency.events_per_second,
+            self.recording, self.playing.as_deref().map_or("null".to_owned(), json_string),
+        )
+    }
+}
+
+// What the endpoint controls, implemented by the app
+pub trait RemoteHandler: Send + Sync {
+    fn status(&self) -> Status;
+    fn command(&self, command: &str) -> Result<(), String>;
+}
+
+fn result_json(result: &Result<(), String>) -> String {
+    match result {
+        Ok(()) => "{\"ok\":true}".to_owned(),
+        Err(err) => format!("{{\"ok\":false,\"error\":{}}}", json_string(err)),
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(header, _)| header.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+}
+
+fn read_request(reader: &mut impl BufRead) -> Result<Request, io::Error> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_owned());
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(invalid("Malformed request line"));
+    };
+    let (method, path) = (method.to_owned(), path.to_owned());
+
+    let mut headers = vec![];
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("Connection closed in the headers"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_owned(), value.trim().to_owned()));
+        }
+    }
+
+    let mut request = Request { method, path, headers, body: vec![] };
+    let len: usize = request.header("Content-Length").and_then(|len| len.parse().ok()).unwrap_or(0);
+    if len > MAX_BODY {
+        return Err(invalid("Request body too large"));
+    }
+    request.body = vec![0; len];
+    reader.read_exact(&mut request.body)?;
+    Ok(request)
+}
+
+pub fn write_response(stream: &mut impl Write, status: &str, content_type: &str, body: &[u8]) -> Result<(), io::Error> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        body.len(),
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+fn serve_websocket(stream: TcpStream, request: &Request, handler: Arc<dyn RemoteHandler>) -> Result<(), io::Error> {
+    let Some(key) = request.header("Sec-WebSocket-Key") else {
+        return write_response(&mut &stream, "400 Bad Request", "text/plain", b"Missing Sec-WebSocket-Key");
+    };
+    write!(
+        &stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        websocket::accept_key(key),
+    )?;
+
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+    let closed = Arc::new(AtomicBool::new(false));
+
+    // Pushes the status whenever it changes, until the connection goes away
+    thread::spawn({
+        let (writer, closed, handler) = (Arc::clone(&writer), Arc::clone(&closed), Arc::clone(&handler));
+        move || {
+            let mut last = String::new();
+            while !closed.load(Ordering::Relaxed) {
+                let status = format!("{{\"type\":\"status\",\"status\":{}}}", handler.status().to_json());
+                if status != last {
+                    if websocket::write_frame(&mut *writer.lock().unwrap(), websocket::TEXT, status.as_bytes()).is_err() {
+                        break;
+                    }
+                    last = status;
+                }
+                thread::sleep(STATUS_INTERVAL);
+            }
+        }
+    });
+
+    let mut reader = BufReader::new(stream);
+    let result = loop {
+        let frame = match websocket::read_frame(&mut reader) {
+            Ok(frame) => frame,
+            Err(err) => break Err(err),
+        };
+        match frame.opcode {
+            websocket::TEXT => {
+                let command = String::from_utf8_lossy(&frame.payload);
+                let result = handler.command(command.trim());
+                let reply = format!("{{\"type\":\"result\",\"command\":{},\"result\":{}}}", json_string(command.trim()), result_json(&result));
+                websocket::write_frame(&mut *writer.lock().unwrap(), websocket::TEXT, reply.as_bytes())?;
+            }
+            websocket::PING => websocket::write_frame(&mut *writer.lock().unwrap(), websocket::PONG, &frame.payload)?,
+            websocket::CLOSE => {
+                let _ = websocket::write_frame(&mut *writer.lock().unwrap(), websocket::CLOSE, &[]);
+                break Ok(());
+            }
+            _ => {}
+        }
+    };
+    closed.store(true, Ordering::Relaxed);
+    result
+}
+
+// Browsers say which page a request or WebSocket comes from, and don't ask before a plain POST or
+// opening a WebSocket, so only pages served from here get through. Scripts and tools send no origin.
+// The host has to be an address or localhost, a website's own name could be pointed at this machine
+fn same_origin(request: &Request) -> bool {
+    let Some(origin) = request.header("Origin") else { return true };
+    let Some(host) = request.header("Host") else { return false };
+    let name = match host.rsplit_once(':') {
+        Some((name, port)) if port.parse::<u16>().is_ok() => name,
+        _ => host,
+    };
+    let local = name.eq_ignore_ascii_case("localhost") || name.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().is_ok();
+    local && origin.eq_ignore_ascii_case(&format!("http://{host}"))
+}
+
+fn handle_client(stream: TcpStream, handler: Arc<dyn RemoteHandler>) -> Result<(), io::Error> {
+    let request = read_request(&mut BufReader::new(&stream))?;
+    log_debug!(App, "Remote: {} {}", request.method, request.path);
+    let mut stream = stream;
+
+    if !same_origin(&request) {
+        log_warn!(App, "Remote: refused a request from {}", request.header("Origin").unwrap_or_default());
+        return write_response(&mut stream, "403 Forbidden", "text/plain", b"Cross-origin requests aren't allowed");
+    }
+
+    // Options for the overlay are read by the page itself
+    let path = request.path.split('?').next().unwrap_or_default();
+    match (request.method.as_str(), path) {
+        ("GET", "/") => write_response(&mut stream, "200 OK", "text/plain; charset=utf-8", USAGE.as_bytes()),
+        ("GET", "/overlay") => write_response(&mut stream, "200 OK", "text/html; charset=utf-8", OVERLAY.as_bytes()),
+        ("GET", "/status") => write_response(&mut stream, "200 OK", "application/json", handler.status().to_json().as_bytes()),
+        ("POST", "/command") => {
+            let command = String::from_utf8_lossy(&request.body);
+            let result = handler.command(command.trim());
+            let status = if result.is_ok() { "200 OK" } else { "400 Bad Request" };
+            write_response(&mut stream, status, "application/json", result_json(&result).as_bytes())
+        }
+        ("GET", "/ws") => serve_websocket(stream, &request, handler),
+        _ => write_response(&mut stream, "404 Not Found", "text/plain", b"Not found"),
+    }
+}
+
+// Starts serving on `address` in the background, returning the address actually bound
+pub fn serve(address: &str, handler: Arc<dyn RemoteHandler>) -> Result<SocketAddr, io::Error> {
+    let listener = TcpListener::bind(address)?;
+    let local = listener.local_addr()?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let handler = Arc::clone(&handler);
+                    thread::spawn(move || {
+                        if let Err(err) = handle_client(stream, handler) {
+                            log_debug!(App, "Remote client failed: {}", err);
+                        }
+                    });
+                }
+                Err(err) => log_warn!(App, "Failed to accept remote connection: {}", err),
+            }
+        }
+    });
+    Ok(local)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct FakeApp {
+        commands: Mutex<Vec<String>>,
+    }
+
+    impl RemoteHandler for FakeApp {
+        fn status(&self) -> Status {
+            Status {
+                port: "Test \"port\"".to_owned(),
+                method: "Generic".to_owned(),
+                held_notes: vec![60, 64],
+                held_keys: vec!["t".to_owned(), "\"".to_owned()],
+                ..Default::default()
+            }
+        }
+
+        fn command(&self, command: &str) -> Result<(), String> {
+            if command == "explode" {
+                return Err(format!("Unknown command {command:?}"));
+            }
+            self.commands.lock().unwrap().push(command.to_owned());
+            Ok(())
+        }
+    }
+
+    fn start() -> (SocketAddr, Arc<FakeApp>) {
+        let app = Arc::new(FakeApp::default());
+        (serve("127.0.0.1:0", app.clone()).unwrap(), app)
+    }
+
+    fn request(address: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn status_json() {
+        let json = FakeApp::default().status().to_json();
+        assert!(json.starts_with("{\"port\":\"Test \\\"port\\\"\",\"method\":\"Generic\""));
+        assert!(json.contains("\"held_notes\":[60,64],\"held_keys\":[\"t\",\"\\\"\"]"));
+        assert!(json.ends_with("\"recording\":false,\"playing\":null}"));
+    }
+
+    #[test]
+    fn http_endpoints() {
+        let (address, app) = start();
+
+        let response = request(address, "GET /status HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: application/json"));
+        assert!(response.ends_with(&app.status().to_json()));
+
+        let response = request(address, "POST /command HTTP/1.1\r\nContent-Length: 14\r\n\r\ntoggle-output\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n") && response.ends_with("{\"ok\":true}"));
+        let response = request(address, "POST /command HTTP/1.1\r\nContent-Length: 7\r\n\r\nexplode");
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n") && response.contains("\"ok\":false"));
+        assert_eq!(*app.commands.lock().unwrap(), vec!["toggle-output"]);
+
+        assert!(request(address, "GET /nothing HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn refuses_other_origins() {
+        let (address, app) = start();
+        let post = |host: &str, origin: &str| request(address, &format!("POST /command HTTP/1.1\r\nHost: {host}\r\nOrigin: {origin}\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\npanic"));
+
+        assert!(post(&address.to_string(), "https://example.com").starts_with("HTTP/1.1 403"));
+        // A website's name pointed at this machine
+        assert!(post("example.com:7879", "http://example.com:7879").starts_with("HTTP/1.1 403"));
+        let websocket = format!("GET /ws HTTP/1.1\r\nHost: {address}\r\nOrigin: https://example.com\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n");
+        assert!(request(address, &websocket).starts_with("HTTP/1.1 403"));
+        assert!(app.commands.lock().unwrap().is_empty());
+
+        // The overlay, served from here
+        let response = post(&address.to_string(), &format!("http://{address}"));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n") && !response.contains("Access-Control-Allow-Origin"));
+        let localhost = format!("localhost:{}", address.port());
+        assert!(post(&localhost, &format!("http://{localhost}")).starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(*app.commands.lock().unwrap(), vec!["panic", "panic"]);
+    }
+
+    #[test]
+    fn overlay_page() {
+        let (address, _) = start();
+        let response = request(address, "GET /overlay?roll=0 HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: text/html; charset=utf-8"));
+        // Runs without anything from outside, following the status over the WebSocket it was served from
+        let page = response.split("\r\n\r\n").nth(1).unwrap();
+        assert!(page.contains("new WebSocket(") && page.contains("/ws"));
+        assert!(!page.contains("http://") && !page.contains("https://"));
+    }
+
+    #[test]
+    fn websocket_session() {
+        let (address, app) = start();
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        stream.write_all(b"GET /ws HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n").unwrap();
+
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "HTTP/1.1 101 Switching Protocols\r\n");
+        let mut accept = None;
+        while line != "\r\n" {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            accept = accept.or(line.strip_prefix("Sec-WebSocket-Accept: ").map(|key| key.trim().to_owned()));
+        }
+        assert_eq!(accept.as_deref(), Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+
+        let status = websocket::read_frame(&mut reader).unwrap();
+        assert!(String::from_utf8(status.payload).unwrap().starts_with("{\"type\":\"status\",\"status\":{\"port\""));
+
+        // Clients mask what they send
+        let mask = [1, 2, 3, 4];
+        let mut frame = vec![0x81, 0x80 | 5];
+        frame.extend(mask);
+        frame.extend(b"panic".iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        stream.write_all(&frame).unwrap();
+
+        let result = websocket::read_frame(&mut reader).unwrap();
+        assert_eq!(String::from_utf8(result.payload).unwrap(), "{\"type\":\"result\",\"command\":\"panic\",\"result\":{\"ok\":true}}");
+        assert_eq!(*app.commands.lock().unwrap(), vec!["panic"]);
+    }
+}
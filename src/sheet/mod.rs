@@ -0,0 +1,305 @@
+// "Virtual piano" sheets, the text players share for songs: every note is the character the
+// output method types for it, written in the order they're played.
+//
+//   tempo: 120
+//   [tuo]y u | | t
+//
+//   [tuo]   a chord, notes pressed together
+//   ty      notes written together follow each other a quarter beat apart
+//   t u     a space is half a beat
+//   t | u   every | is a whole beat, so t | | u is two
+//   ~t      one of the extra 88-key notes of Piano Visualizations, typed with ctrl
+//
+// Line breaks count as spaces. The tempo line is optional, 120 beats per minute otherwise, and
+// can also change the tempo midway. Lines starting with # are comments.
+
+use crate::midi::TimedMessage;
+use crate::output_methods::{generic_char_for_note, pv_char_for_note};
+
+pub const DEFAULT_BPM: f64 = 120.0;
+
+const CTRL: char = '~';
+// Lines are broken at the first pause after this many characters
+const LINE_LENGTH: usize = 64;
+// Longest pause written, in beats
+const MAX_PAUSE: u32 = 8;
+// Sheets don't say how hard notes are played
+const VELOCITY: u8 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mapping {
+    Generic,
+    Pv, // Piano Visualizations, with its 88-key notes
+}
+
+impl Mapping {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "generic" => Some(Mapping::Generic),
+            "pv" | "piano-visualizations" => Some(Mapping::Pv),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mapping::Generic => "Generic",
+            Mapping::Pv => "Piano Visualizations",
+        }
+    }
+
+    // How `note` is written, None when the mapping has no key for it
+    fn token(self, note: u8) -> Option<String> {
+        match self {
+            Mapping::Generic => generic_char_for_note(note).map(String::from),
+            Mapping::Pv => match generic_char_for_note(note) {
+                Some(c) => Some(c.to_string()),
+                None => pv_char_for_note(note).map(|c| format!("{CTRL}{c}")),
+            },
+        }
+    }
+}
+
+// The note written as `token`, whichever of the two mappings it's from
+fn note_for_token(token: &str) -> Option<u8> {
+    (0..128).find(|&note| Mapping::Pv.token(note).as_deref() == Some(token))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transcription {
+    pub text: String,
+    pub skipped: usize, // notes the mapping has no key for
+}
+
+// What goes between two chords `beats` apart
+fn separator(beats: f64) -> String {
+    if beats < 0.375 {
+        String::new()
+    } else if beats < 0.75 {
+        " ".to_owned()
+    } else {
+        let pauses = (beats.round() as u32).clamp(1, MAX_PAUSE) as usize;
+        format!(" {} ", vec!["|"; pauses].join(" "))
+    }
+}
+
+// Writes the notes of `messages` as a sheet. Chords are notes starting within a sixteenth of a
+// beat of each other, how long notes are held and pedals are left out
+pub fn transcribe(messages: &[TimedMessage], mapping: Mapping, bpm: f64) -> Transcription {
+    let beat = 60_000_000.0 / bpm;
+    let mut skipped = 0;
+
+    let mut chords: Vec<(u64, Vec<u8>)> = vec![];
+    for TimedMessage { time, message } in messages {
+        let &[status, note, velocity] = message.as_slice() else { continue };
+        if status & 0xF0 != 0x90 || velocity == 0 {
+            continue;
+        }
+        match chords.last_mut() {
+            Some((start, notes)) if time.saturating_sub(*start) as f64 <= beat / 16.0 => notes.push(note),
+            _ => chords.push((*time, vec![note])),
+        }
+    }
+
+    let mut text = format!("tempo: {bpm}\n");
+    let mut line_start = text.len();
+    let mut previous: Option<u64> = None;
+    for (start, mut notes) in chords {
+        notes.sort_unstable();
+        notes.dedup();
+        let tokens: Vec<String> = notes.iter().filter_map(|&note| mapping.token(note)).collect();
+        skipped += notes.len() - tokens.len();
+        if tokens.is_empty() {
+            continue;
+        }
+
+        if let Some(previous) = previous {
+            let separator = separator(start.saturating_sub(previous) as f64 / beat);
+            if text.len() - line_start >= LINE_LENGTH && !separator.is_empty() {
+                text.push_str(separator.trim_end());
+                text.push('\n');
+                line_start = text.len();
+            } else {
+                text.push_str(&separator);
+            }
+        }
+        match tokens.len() {
+            1 => text.push_str(&tokens[0]),
+            _ => text.push_str(&format!("[{}]", tokens.concat())),
+        }
+        previous = Some(start);
+    }
+    text.push('\n');
+
+    Transcription { text, skipped }
+}
+
+// "tempo: 90", "BPM = 90" and the like
+fn parse_tempo(line: &str) -> Option<f64> {
+    let line = line.to_ascii_lowercase();
+    let value = line.strip_prefix("tempo").or_else(|| line.strip_prefix("bpm"))?.trim_start();
+    let value = value.strip_prefix(':').or_else(|| value.strip_prefix('='))?;
+    value.trim().parse().ok().filter(|bpm: &f64| *bpm > 0.0)
+}
+
+// Reads a sheet into the notes it plays. Notes are held until the next note or chord starts,
+// the last one for a beat
+pub fn parse(text: &str) -> Result<Vec<TimedMessage>, String> {
+    let mut beat = 60_000_000.0 / DEFAULT_BPM;
+    let mut chords: Vec<(u64, Vec<u8>)> = vec![];
+    let mut time = 0.0;
+    // Separators since the last chord
+    let (mut pipes, mut space) = (0, false);
+
+    for (number, line) in text.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('#') {
+            continue;
+        }
+        if let Some(bpm) = parse_tempo(trimmed) {
+            beat = 60_000_000.0 / bpm;
+            continue;
+        }
+
+        let error = |column: usize, message: String| format!("Line {}, column {}: {message}", number + 1, column + 1);
+        let mut chars = line.chars().enumerate();
+        let mut chord: Option<Vec<u8>> = None;
+        while let Some((column, c)) = chars.next() {
+            let note = match c {
+                ' ' | '\t' if chord.is_none() => {
+                    space = true;
+                    continue;
+                }
+                '|' if chord.is_none() => {
+                    pipes += 1;
+                    continue;
+                }
+                '[' if chord.is_none() => {
+                    chord = Some(vec![]);
+                    continue;
+                }
+                ']' if chord.is_some() => None,
+                CTRL => match chars.next() {
+                    Some((_, c)) => Some(note_for_token(&format!("{CTRL}{c}")).ok_or_else(|| error(column, format!("{CTRL}{c} isn't an 88-key note")))?),
+                    None => return Err(error(column, format!("{CTRL} without a note"))),
+                },
+                c => Some(note_for_token(&c.to_string()).ok_or_else(|| error(column, format!("Unexpected {c:?}")))?),
+            };
+
+            // The note, or the chord that just closed
+            let notes = match (note, chord.as_mut()) {
+                (Some(note), Some(chord)) => {
+                    chord.push(note);
+                    continue;
+                }
+                (Some(note), None) => vec![note],
+                (None, _) => chord.take().unwrap_or_default(),
+            };
+            if notes.is_empty() {
+                continue;
+            }
+            if !chords.is_empty() {
+                let beats = match (pipes, space) {
+                    (0, false) => 0.25,
+                    (0, true) => 0.5,
+                    (pipes, _) => pipes as f64,
+                };
+                time += beats * beat;
+            }
+            (pipes, space) = (0, false);
+            chords.push((time as u64, notes));
+        }
+        if chord.is_some() {
+            return Err(error(line.chars().count(), "[ without a closing ]".to_owned()));
+        }
+        space = true;
+    }
+
+    let mut messages = vec![];
+    let mut held: Vec<u8> = vec![];
+    for (time, notes) in chords {
+        messages.extend(held.drain(..).map(|note| TimedMessage { time, message: vec![0x80, note, 0] }));
+        messages.extend(notes.iter().map(|&note| TimedMessage { time, message: vec![0x90, note, VELOCITY] }));
+        held = notes;
+    }
+    let end = messages.last().map_or(0, |message| message.time) + beat as u64;
+    messages.extend(held.into_iter().map(|note| TimedMessage { time: end, message: vec![0x80, note, 0] }));
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notes(notes: &[(u64, u8)]) -> Vec<TimedMessage> {
+        notes.iter().flat_map(|&(millis, note)| [
+            TimedMessage { time: millis * 1000, message: vec![0x90, note, 100] },
+            TimedMessage { time: millis * 1000 + 100_000, message: vec![0x80, note, 0] },
+        ]).collect()
+    }
+
+    #[test]
+    fn writes_chords_and_pauses() {
+        let song = notes(&[(0, 60), (10, 67), (20, 64), (125, 62), (375, 64), (1375, 60), (1400, 20), (1875, 100)]);
+        let mut expected = Transcription { text: "tempo: 120\n[tuo]y u | | t\n".to_owned(), skipped: 2 };
+        assert_eq!(transcribe(&song, Mapping::Generic, 120.0), expected);
+
+        // Pedals and note offs don't change anything
+        let mut with_pedals = song.clone();
+        with_pedals.insert(1, TimedMessage { time: 5000, message: vec![0xB0, 64, 127] });
+        assert_eq!(transcribe(&with_pedals, Mapping::Generic, 120.0), expected);
+
+        // Twice as slow, the same time is half the beats
+        expected.text = "tempo: 60\n[tuo]yu | t\n".to_owned();
+        assert_eq!(transcribe(&song, Mapping::Generic, 60.0), expected);
+    }
+
+    #[test]
+    fn pv_writes_88_key_notes() {
+        let song = notes(&[(0, 21), (0, 36), (500, 108), (1000, 35)]);
+        let expected = Transcription { text: "tempo: 120\n[~11] | ~j | ~t\n".to_owned(), skipped: 0 };
+        assert_eq!(transcribe(&song, Mapping::Pv, 120.0), expected);
+    }
+
+    #[test]
+    fn breaks_long_lines_at_pauses() {
+        let song: Vec<(u64, u8)> = (0..200).map(|index| (index * 250, 48 + (index % 24) as u8)).collect();
+        let text = transcribe(&notes(&song), Mapping::Generic, 120.0).text;
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines.len() > 3);
+        assert!(lines.iter().all(|line| line.len() <= LINE_LENGTH + 1 && line == &line.trim()));
+    }
+
+    #[test]
+    fn reads_sheets() {
+        let sheet = "# Test song\nBPM: 60\n[tu] y\n| ~1\n";
+        let expected = vec![
+            TimedMessage { time: 0, message: vec![0x90, 60, 100] },
+            TimedMessage { time: 0, message: vec![0x90, 64, 100] },
+            TimedMessage { time: 500_000, message: vec![0x80, 60, 0] },
+            TimedMessage { time: 500_000, message: vec![0x80, 64, 0] },
+            TimedMessage { time: 500_000, message: vec![0x90, 62, 100] },
+            TimedMessage { time: 1_500_000, message: vec![0x80, 62, 0] },
+            TimedMessage { time: 1_500_000, message: vec![0x90, 21, 100] },
+            TimedMessage { time: 2_500_000, message: vec![0x80, 21, 0] },
+        ];
+        assert_eq!(parse(sheet).unwrap(), expected);
+    }
+
+    #[test]
+    fn reads_written_sheets_back() {
+        let song = notes(&[(0, 60), (0, 64), (0, 67), (125, 62), (375, 64), (1375, 21), (1625, 108), (2000, 96)]);
+        let sheet = transcribe(&song, Mapping::Pv, 120.0).text;
+        assert_eq!(sheet, "tempo: 120\n[tuo]y u | | ~1 ~j | m\n");
+        assert_eq!(transcribe(&parse(&sheet).unwrap(), Mapping::Pv, 120.0).text, sheet);
+    }
+
+    #[test]
+    fn rejects_broken_sheets() {
+        assert_eq!(parse("t y\n  [tu"), Err("Line 2, column 6: [ without a closing ]".to_owned()));
+        assert_eq!(parse("t - y"), Err("Line 1, column 3: Unexpected '-'".to_owned()));
+        assert_eq!(parse("~k"), Err("Line 1, column 1: ~k isn't an 88-key note".to_owned()));
+        assert_eq!(parse("[t[y]]").unwrap_err(), "Line 1, column 3: Unexpected '['");
+        assert_eq!(parse("\n# nothing\n[]"), Ok(vec![]));
+    }
+}